@@ -0,0 +1,168 @@
+use std::{error::Error, fmt::Display, str::FromStr};
+
+use mediastream_rs::format::{M3uMedia, attributes};
+use regex::Regex;
+use smol_str::SmolStr;
+
+/// Which part of a media an expression looks at
+#[derive(Clone, Debug, PartialEq)]
+pub enum Field {
+    Name,
+    Location,
+    Duration,
+    Attribute(SmolStr),
+}
+
+#[derive(Clone, Debug)]
+pub enum Operator {
+    Equal(SmolStr),
+    NotEqual(SmolStr),
+    Match(Regex),
+    NotMatch(Regex),
+}
+
+/// A filter expression, such as `group=News`, `name~(?i)^bbc` or `tvg-id!=`
+///
+/// Fields are `name`, `location`, `duration`, `group` (short for `group-title`)
+/// or any attribute name.
+#[derive(Clone, Debug)]
+pub struct Expression {
+    pub field: Field,
+    pub operator: Operator,
+}
+
+#[derive(Debug)]
+pub enum ExpressionParseError {
+    MissingOperator,
+    MissingField,
+    InvalidRegex(regex::Error),
+}
+
+impl Display for ExpressionParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingOperator => write!(f, "Expected one of `=`, `!=`, `~`, `!~`"),
+            Self::MissingField => write!(f, "Field name is missing"),
+            Self::InvalidRegex(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ExpressionParseError {}
+
+impl FromStr for Expression {
+    type Err = ExpressionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let position = s
+            .find(['=', '~', '!'])
+            .ok_or(ExpressionParseError::MissingOperator)?;
+
+        let field = s[..position].trim();
+        let rest = &s[position..];
+        let (operator, value) = if let Some(value) = rest.strip_prefix("!=") {
+            ("!=", value)
+        } else if let Some(value) = rest.strip_prefix("!~") {
+            ("!~", value)
+        } else if let Some(value) = rest.strip_prefix('=') {
+            ("=", value)
+        } else if let Some(value) = rest.strip_prefix('~') {
+            ("~", value)
+        } else {
+            return Err(ExpressionParseError::MissingOperator);
+        };
+
+        let field = match field {
+            "" => return Err(ExpressionParseError::MissingField),
+            "name" => Field::Name,
+            "location" => Field::Location,
+            "duration" => Field::Duration,
+            "group" => Field::Attribute(attributes::GROUP_TITLE.into()),
+            other => Field::Attribute(other.into()),
+        };
+
+        let operator = match operator {
+            "=" => Operator::Equal(value.into()),
+            "!=" => Operator::NotEqual(value.into()),
+            "~" => Operator::Match(Regex::new(value).map_err(ExpressionParseError::InvalidRegex)?),
+            _ => Operator::NotMatch(Regex::new(value).map_err(ExpressionParseError::InvalidRegex)?),
+        };
+
+        Ok(Self { field, operator })
+    }
+}
+
+impl Expression {
+    pub fn matches(&self, media: &M3uMedia) -> bool {
+        let value = match &self.field {
+            Field::Name => media.name.clone(),
+            Field::Location => Some(media.location.clone()),
+            Field::Duration => media.duration.as_ref().map(|x| x.text.clone()),
+            Field::Attribute(key) => media.attributes.get(key).cloned(),
+        };
+
+        // a missing field never equals or matches anything
+        match &self.operator {
+            Operator::Equal(expected) => value.as_ref() == Some(expected),
+            Operator::NotEqual(expected) => value.as_ref() != Some(expected),
+            Operator::Match(regex) => value.is_some_and(|x| regex.is_match(&x)),
+            Operator::NotMatch(regex) => !value.is_some_and(|x| regex.is_match(&x)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mediastream_rs::parse_str;
+
+    use crate::filter::{Expression, ExpressionParseError};
+
+    #[test]
+    fn test_parse_expression() {
+        assert!("group=News".parse::<Expression>().is_ok());
+        assert!("name!~^A".parse::<Expression>().is_ok());
+        assert!(matches!(
+            "name".parse::<Expression>(),
+            Err(ExpressionParseError::MissingOperator)
+        ));
+        assert!(matches!(
+            "=A".parse::<Expression>(),
+            Err(ExpressionParseError::MissingField)
+        ));
+        assert!(matches!(
+            "name~(".parse::<Expression>(),
+            Err(ExpressionParseError::InvalidRegex(_))
+        ));
+    }
+
+    #[test]
+    fn test_match_expression() {
+        let playlist = parse_str(
+            r#"#EXTM3U
+#EXTINF:-1 group-title="News",BBC One
+http://example.com/A.m3u8
+#EXTINF:-1,Movies
+http://example.com/B.m3u8"#,
+        )
+        .unwrap()
+        .to_owned_playlist();
+        let news = &playlist.medias[0];
+        let movies = &playlist.medias[1];
+
+        let group: Expression = "group=News".parse().unwrap();
+        assert!(group.matches(news));
+        assert!(!group.matches(movies));
+
+        let not_group: Expression = "group!=News".parse().unwrap();
+        assert!(!not_group.matches(news));
+        assert!(not_group.matches(movies));
+
+        let name: Expression = "name~(?i)^bbc".parse().unwrap();
+        assert!(name.matches(news));
+        assert!(!name.matches(movies));
+
+        let location: Expression = "location!~B\\.m3u8$".parse().unwrap();
+        assert!(location.matches(news));
+        assert!(!location.matches(movies));
+    }
+}
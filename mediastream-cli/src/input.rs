@@ -0,0 +1,38 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use mediastream_rs::{format::M3uPlaylist, parse_str};
+
+/// Read the whole input, `-` or no path means stdin
+pub fn read_input(path: Option<&Path>) -> Result<String> {
+    match path {
+        Some(path) if path != Path::new("-") => {
+            fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))
+        }
+        _ => {
+            let mut buffer = String::new();
+            io::stdin()
+                .read_to_string(&mut buffer)
+                .context("Failed to read stdin")?;
+            Ok(buffer)
+        }
+    }
+}
+
+/// Read and parse a playlist, `-` or no path means stdin
+pub fn read_playlist(path: Option<&Path>) -> Result<M3uPlaylist> {
+    let content = read_input(path)?;
+    let playlist = parse_str(&content).with_context(|| {
+        format!(
+            "Failed to parse {}",
+            path.map(|x| x.display().to_string())
+                .unwrap_or_else(|| "-".into())
+        )
+    })?;
+
+    Ok(playlist.to_owned_playlist())
+}
@@ -0,0 +1,188 @@
+use std::{collections::HashMap, fmt::Display};
+
+use mediastream_rs::format::directives;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found in a playlist, `line` starts from 1
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}: {}", self.line, self.severity, self.message)
+    }
+}
+
+/// Check the playlist line by line
+pub fn lint(content: &str) -> Vec<Diagnostic> {
+    let mut result = Vec::new();
+    let mut report = |line, severity, message: String| {
+        result.push(Diagnostic {
+            line,
+            severity,
+            message,
+        })
+    };
+
+    let mut header_checked = false;
+    let mut pending_extinf: Option<usize> = None;
+    let mut locations: HashMap<&str, usize> = HashMap::new();
+
+    for (index, line) in content.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if !header_checked {
+            header_checked = true;
+            if !line.starts_with(directives::EXTM3U) {
+                report(number, Severity::Warning, "missing #EXTM3U header".into());
+            } else {
+                continue;
+            }
+        }
+
+        if line.starts_with(directives::EXTM3U) {
+            report(number, Severity::Warning, "repeated #EXTM3U header".into());
+            continue;
+        }
+
+        if let Some(value) = line.strip_prefix(directives::EXTINF) {
+            if let Some(previous) = pending_extinf {
+                report(
+                    previous,
+                    Severity::Warning,
+                    "#EXTINF is not followed by a media location".into(),
+                );
+            }
+            pending_extinf = Some(number);
+
+            let Some(value) = value.strip_prefix(':') else {
+                report(number, Severity::Error, "#EXTINF without a value".into());
+                continue;
+            };
+
+            let (info, name) = match value.split_once(',') {
+                Some((info, name)) => (info, Some(name)),
+                None => (value, None),
+            };
+            let duration = info.split(' ').next().unwrap_or_default();
+            if duration.parse::<f32>().is_err() {
+                report(
+                    number,
+                    Severity::Warning,
+                    format!("invalid duration `{}`, treated as unknown", duration),
+                );
+            }
+            if name.is_none_or(|x| x.trim().is_empty()) {
+                report(number, Severity::Warning, "media has no name".into());
+            }
+            continue;
+        }
+
+        if line.starts_with('#') {
+            continue;
+        }
+
+        // media location
+        if pending_extinf.take().is_none() {
+            report(number, Severity::Warning, "media without #EXTINF".into());
+        }
+        // paths may contain spaces, URLs should have them encoded
+        if has_url_scheme(line) && line.contains(char::is_whitespace) {
+            report(
+                number,
+                Severity::Warning,
+                "media URL contains whitespace".into(),
+            );
+        }
+        if let Some(first) = locations.get(line) {
+            report(
+                number,
+                Severity::Warning,
+                format!("duplicated media location, first seen at line {}", first),
+            );
+        } else {
+            locations.insert(line, number);
+        }
+    }
+
+    if !header_checked {
+        report(1, Severity::Error, "playlist is empty".into());
+    }
+    if let Some(previous) = pending_extinf {
+        report(
+            previous,
+            Severity::Warning,
+            "#EXTINF is not followed by a media location".into(),
+        );
+    }
+
+    result
+}
+
+fn has_url_scheme(location: &str) -> bool {
+    location.split_once("://").is_some_and(|(scheme, _)| {
+        !scheme.is_empty()
+            && scheme
+                .chars()
+                .all(|x| x.is_ascii_alphanumeric() || matches!(x, '+' | '-' | '.'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lint::{Severity, lint};
+
+    #[test]
+    fn test_lint_valid() {
+        let result = lint("#EXTM3U\n#EXTINF:-1,A\nA.ts\n#EXTINF:1,B\nMy Show/B 1.ts\n");
+        assert!(result.is_empty());
+
+        let result = lint("#EXTM3U\n#EXTINF:-1,A\nhttp://example.com/A 1.ts\n");
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].message, "media URL contains whitespace");
+    }
+
+    #[test]
+    fn test_lint_problems() {
+        let result = lint("A.ts\n#EXTINF:x,A\n#EXTINF:1,\nA.ts\n#EXTINF:1,C\n");
+        let found = result
+            .iter()
+            .map(|x| (x.line, x.severity))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            found,
+            vec![
+                (1, Severity::Warning), // missing header
+                (1, Severity::Warning), // media without #EXTINF
+                (2, Severity::Warning), // invalid duration
+                (2, Severity::Warning), // #EXTINF without location
+                (3, Severity::Warning), // no name
+                (4, Severity::Warning), // duplicated
+                (5, Severity::Warning), // #EXTINF without location
+            ]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use std::{collections::HashSet, path::PathBuf, process::ExitCode};
+
+use anyhow::{Result, bail};
+use clap::{Args, Parser, Subcommand};
+use mediastream_rs::format::M3uPlaylist;
+use url::Url;
+
+use crate::{
+    filter::Expression,
+    input::{read_input, read_playlist},
+    lint::{Severity, lint},
+    output::{OutputFormat, render, write_output},
+};
+
+mod filter;
+mod input;
+mod lint;
+mod output;
+
+/// Maintain m3u/m3u8 playlists, inputs are files or `-` for stdin
+#[derive(Parser)]
+#[command(name = "m3u", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Lint a playlist and print the diagnostics
+    Validate {
+        input: Option<PathBuf>,
+        /// Fail on warnings too
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Normalize and pretty-print a playlist
+    Fmt {
+        input: Option<PathBuf>,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Keep the medias matching the expressions, such as `group=News`, `name~(?i)bbc`, `tvg-id!=`
+    Filter {
+        input: Option<PathBuf>,
+        /// Filter expression, `=`, `!=`, `~` (regex) or `!~`, all of them must match by default
+        #[arg(short = 'e', long = "expr", required = true)]
+        expressions: Vec<Expression>,
+        /// Keep the medias matching any of the expressions
+        #[arg(long)]
+        any: bool,
+        /// Drop the matching medias instead
+        #[arg(short = 'v', long)]
+        invert: bool,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Concatenate playlists, the first one wins on playlist attributes
+    Merge {
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        /// Drop medias whose location has been seen before
+        #[arg(long)]
+        dedupe: bool,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Make relative media locations absolute
+    Resolve {
+        input: Option<PathBuf>,
+        /// The URL that relative locations are relative to
+        #[arg(short, long)]
+        base: Url,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Convert a playlist to another format
+    Convert {
+        input: Option<PathBuf>,
+        #[arg(short, long, value_enum)]
+        to: OutputFormat,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+}
+
+#[derive(Args)]
+struct OutputArgs {
+    /// Output file, stdout by default
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+impl OutputArgs {
+    fn write(&self, playlist: &M3uPlaylist, format: OutputFormat) -> Result<()> {
+        write_output(self.output.as_deref(), &render(playlist, format))
+    }
+}
+
+fn validate(input: Option<PathBuf>, strict: bool) -> Result<ExitCode> {
+    let content = read_input(input.as_deref())?;
+    let diagnostics = lint(&content);
+
+    for diagnostic in diagnostics.iter() {
+        println!("{}", diagnostic);
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|x| x.severity == Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+    eprintln!("{} error(s), {} warning(s)", errors, warnings);
+
+    if errors > 0 || (strict && warnings > 0) {
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn resolve(playlist: &mut M3uPlaylist, base: &Url) -> Result<()> {
+    for media in playlist.medias.iter_mut() {
+        match Url::parse(&media.location) {
+            Ok(_) => {}
+            Err(url::ParseError::RelativeUrlWithoutBase) => {
+                media.location = base.join(&media.location)?.as_str().into();
+            }
+            Err(e) => bail!("Invalid media location {}: {}", media.location, e),
+        }
+    }
+
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<ExitCode> {
+    match cli.command {
+        Command::Validate { input, strict } => return validate(input, strict),
+        Command::Fmt { input, output } => {
+            let playlist = read_playlist(input.as_deref())?;
+            output.write(&playlist, OutputFormat::M3u)?;
+        }
+        Command::Filter {
+            input,
+            expressions,
+            any,
+            invert,
+            output,
+        } => {
+            let mut playlist = read_playlist(input.as_deref())?;
+            playlist.medias.retain(|media| {
+                let matched = if any {
+                    expressions.iter().any(|x| x.matches(media))
+                } else {
+                    expressions.iter().all(|x| x.matches(media))
+                };
+                matched != invert
+            });
+            output.write(&playlist, OutputFormat::M3u)?;
+        }
+        Command::Merge {
+            inputs,
+            dedupe,
+            output,
+        } => {
+            let mut result = M3uPlaylist::default();
+            let mut seen = HashSet::new();
+            for input in inputs.iter() {
+                let playlist = read_playlist(Some(input))?;
+                if result.title.is_none() {
+                    result.title = playlist.title;
+                }
+                for (key, value) in playlist.attributes {
+                    result.attributes.entry(key).or_insert(value);
+                }
+                for media in playlist.medias {
+                    if dedupe && !seen.insert(media.location.clone()) {
+                        continue;
+                    }
+                    result.medias.push(media);
+                }
+            }
+            output.write(&result, OutputFormat::M3u)?;
+        }
+        Command::Resolve {
+            input,
+            base,
+            output,
+        } => {
+            let mut playlist = read_playlist(input.as_deref())?;
+            resolve(&mut playlist, &base)?;
+            output.write(&playlist, OutputFormat::M3u)?;
+        }
+        Command::Convert { input, to, output } => {
+            let playlist = read_playlist(input.as_deref())?;
+            output.write(&playlist, to)?;
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use mediastream_rs::format::M3uPlaylist;
+use serde_json::{Value, json};
+use smol_str::SmolStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    M3u,
+    Pls,
+    Json,
+}
+
+/// Write the content to the output, `-` or no path means stdout
+pub fn write_output(path: Option<&Path>, content: &str) -> Result<()> {
+    match path {
+        Some(path) if path != Path::new("-") => {
+            fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+        }
+        _ => {
+            io::stdout()
+                .write_all(content.as_bytes())
+                .context("Failed to write stdout")?;
+            Ok(())
+        }
+    }
+}
+
+pub fn render(playlist: &M3uPlaylist, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::M3u => playlist.to_string(),
+        OutputFormat::Pls => render_pls(playlist),
+        OutputFormat::Json => render_json(playlist),
+    }
+}
+
+fn sorted<V>(map: &HashMap<SmolStr, V>) -> BTreeMap<&SmolStr, &V> {
+    map.iter().collect()
+}
+
+pub fn render_pls(playlist: &M3uPlaylist) -> String {
+    let mut result = String::from("[playlist]\n");
+
+    for (index, media) in playlist.medias.iter().enumerate() {
+        let number = index + 1;
+        _ = writeln!(result, "File{}={}", number, media.location);
+        if let Some(name) = &media.name {
+            _ = writeln!(result, "Title{}={}", number, name);
+        }
+
+        // PLS only accepts whole seconds, and -1 for unknown length
+        let length = match media.get_duration() {
+            Some(duration) if duration >= 0.0 => duration.round() as i64,
+            _ => -1,
+        };
+        _ = writeln!(result, "Length{}={}", number, length);
+    }
+
+    _ = writeln!(result, "NumberOfEntries={}", playlist.medias.len());
+    result.push_str("Version=2\n");
+
+    result
+}
+
+pub fn render_json(playlist: &M3uPlaylist) -> String {
+    let medias = playlist
+        .medias
+        .iter()
+        .map(|media| {
+            json!({
+                "name": media.name,
+                "duration": media.get_duration(),
+                "location": media.location,
+                "attributes": sorted(&media.attributes),
+                "extensionData": sorted(&media.extension_data),
+            })
+        })
+        .collect::<Vec<Value>>();
+
+    let value = json!({
+        "title": playlist.title,
+        "attributes": sorted(&playlist.attributes),
+        "medias": medias,
+    });
+
+    let mut result = serde_json::to_string_pretty(&value).expect("Serialize json");
+    result.push('\n');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use mediastream_rs::parse_str;
+
+    use crate::output::{OutputFormat, render, render_json, render_pls};
+
+    const DATA: &str = r#"#EXTM3U x-tvg-url="test"
+#EXTINF:-1 tvg-id="b" group-title="News",B
+http://example.com/B.m3u8
+#EXTINF:10,A
+http://example.com/A.m3u8
+"#;
+
+    #[test]
+    fn test_render_m3u_is_stable() {
+        let playlist = parse_str(DATA).unwrap().to_owned_playlist();
+        let result = render(&playlist, OutputFormat::M3u);
+
+        assert!(result.contains("#EXTINF:-1 group-title=\"News\" tvg-id=\"b\",B\n"));
+        assert_eq!(
+            result,
+            render(
+                &parse_str(&result).unwrap().to_owned_playlist(),
+                OutputFormat::M3u
+            )
+        );
+    }
+
+    #[test]
+    fn test_render_pls() {
+        let playlist = parse_str(DATA).unwrap().to_owned_playlist();
+        let result = render_pls(&playlist);
+
+        assert!(result.starts_with("[playlist]\n"));
+        assert!(result.contains("File2=http://example.com/A.m3u8\nTitle2=A\nLength2=10\n"));
+        assert!(result.contains("Length1=-1\n"));
+        assert!(result.contains("NumberOfEntries=2\n"));
+    }
+
+    #[test]
+    fn test_render_json() {
+        let playlist = parse_str(DATA).unwrap().to_owned_playlist();
+        let value: serde_json::Value = serde_json::from_str(&render_json(&playlist)).unwrap();
+
+        assert_eq!(value["attributes"]["x-tvg-url"], "test");
+        assert_eq!(value["medias"][0]["attributes"]["group-title"], "News");
+        assert_eq!(value["medias"][1]["name"], "A");
+    }
+}
@@ -1,29 +1,34 @@
-use std::fmt::Display;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+};
+
+use smol_str::SmolStr;
 
 use crate::format::{M3uMedia, M3uPlaylist, directives};
 
+/// Attributes and directives are written sorted, so the output is stable between runs
+fn sorted<V>(map: &HashMap<SmolStr, V>) -> BTreeMap<&SmolStr, &V> {
+    map.iter().collect()
+}
+
 impl Display for M3uPlaylist {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // header
         write!(f, "{}", directives::EXTM3U)?;
-        for (key, value) in self.attributes.iter() {
+        for (key, value) in sorted(&self.attributes) {
             write!(f, " {}=\"{}\"", key, value)?;
         }
-        write!(f, "\n")?;
+        writeln!(f)?;
 
         // title
-        if self.title.is_some() {
-            writeln!(
-                f,
-                "{}:{}",
-                directives::PLAYLIST,
-                self.title.as_ref().unwrap()
-            )?;
+        if let Some(title) = &self.title {
+            writeln!(f, "{}:{}", directives::PLAYLIST, title)?;
         }
 
         // medias
         for it in self.medias.iter() {
-            write!(f, "\n")?;
+            writeln!(f)?;
             it.fmt(f)?;
         }
 
@@ -34,7 +39,7 @@ impl Display for M3uPlaylist {
 impl Display for M3uMedia {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // extension data
-        for (key, value) in self.extension_data.iter() {
+        for (key, value) in sorted(&self.extension_data) {
             if value.is_none() {
                 writeln!(f, "{}", key)?;
             } else {
@@ -43,16 +48,19 @@ impl Display for M3uMedia {
         }
 
         // #EXTINF:duration attributes...,name
-        write!(f, "{}:{}", directives::EXTINF, self.duration)?;
-        for (key, value) in self.attributes.iter() {
+        match &self.duration {
+            Some(duration) => write!(f, "{}:{}", directives::EXTINF, duration)?,
+            None => write!(f, "{}:-1", directives::EXTINF)?,
+        }
+        for (key, value) in sorted(&self.attributes) {
             write!(f, " {}=\"{}\"", key, value)?;
         }
 
         write!(f, ",")?;
-        if self.name.is_some() {
-            write!(f, "{}", self.name.as_ref().unwrap())?;
+        if let Some(name) = &self.name {
+            write!(f, "{}", name)?;
         }
-        write!(f, "\n")?;
+        writeln!(f)?;
 
         writeln!(f, "{}", self.location)?;
 
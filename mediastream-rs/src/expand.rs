@@ -0,0 +1,359 @@
+use std::{collections::HashMap, error::Error, fmt::Display, future::Future, vec};
+
+use smol_str::SmolStr;
+use url::Url;
+
+use crate::{
+    ParseError,
+    format::{M3uMedia, M3uPlaylist, attributes, directives},
+    parse_str,
+};
+
+/// Directives that only appear in HLS playlists, a child containing them is a stream, not a list
+const HLS_DIRECTIVES: [&str; 3] = [
+    directives::EXT_X_TARGETDURATION,
+    directives::EXT_X_MEDIA_SEQUENCE,
+    "#EXT-X-STREAM-INF",
+];
+
+/// Inline the child playlists of a "list of lists".
+///
+/// The fetcher is called with the absolute location of each child playlist,
+/// and should return its content.
+///
+/// Example:
+/// ```rust
+/// use mediastream_rs::{Expander, parse_str};
+///
+/// # futures::executor::block_on(async {
+/// let root = parse_str(r#"#EXTM3U
+/// #EXTINF:-1 group-title="Sports",Sports
+/// sports.m3u"#).unwrap().to_owned_playlist();
+///
+/// let mut expander = Expander::new(|location| async move {
+///     assert_eq!(location, "http://example.com/sports.m3u");
+///     Ok::<_, std::io::Error>("#EXTM3U\n#EXTINF:-1,Match\nmatch.m3u8".to_string())
+/// });
+/// let expanded = expander.expand(root, Some("http://example.com/")).await;
+///
+/// let media = &expanded.playlist.medias[0];
+/// assert_eq!(media.location, "http://example.com/match.m3u8");
+/// assert_eq!(media.attributes.get("group-title").unwrap(), "Sports");
+/// # });
+/// ```
+pub struct Expander<F> {
+    fetcher: F,
+    max_depth: usize,
+    inherited_attributes: Vec<SmolStr>,
+    child_filter: fn(&M3uMedia) -> bool,
+}
+
+/// The result of [`Expander::expand`]
+pub struct Expanded<E> {
+    /// The playlist with child playlists inlined
+    pub playlist: M3uPlaylist,
+    /// Children that failed to expand, they are kept as they were, except cycles that are dropped
+    pub failures: Vec<ExpandFailure<E>>,
+}
+
+pub struct ExpandFailure<E> {
+    /// Absolute location of the child playlist
+    pub location: SmolStr,
+    pub error: ExpandError<E>,
+}
+
+/// Error occurred while expanding a child playlist
+#[derive(Debug)]
+pub enum ExpandError<E> {
+    /// The fetcher failed
+    Fetch(E),
+    /// The content isn't a playlist
+    Parse(ParseError),
+    /// The child is one of its own ancestors
+    Cycle,
+}
+
+impl<E: Display> Display for ExpandError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fetch(e) => write!(f, "Failed to fetch: {}", e),
+            Self::Parse(e) => write!(f, "Failed to parse: {}", e),
+            Self::Cycle => write!(f, "Playlist includes itself"),
+        }
+    }
+}
+
+impl<E: Error> Error for ExpandError<E> {}
+
+/// Whether the location ends with `.m3u`, ignoring query and fragment
+pub fn is_m3u_location(media: &M3uMedia) -> bool {
+    let path = media.location.split(['?', '#']).next().unwrap_or_default();
+    path.to_ascii_lowercase().ends_with(".m3u")
+}
+
+struct Frame {
+    location: Option<SmolStr>,
+    medias: vec::IntoIter<M3uMedia>,
+    base: Option<Url>,
+    inherited: HashMap<SmolStr, SmolStr>,
+    depth: usize,
+}
+
+impl<F, Fut, E> Expander<F>
+where
+    F: FnMut(SmolStr) -> Fut,
+    Fut: Future<Output = Result<String, E>>,
+{
+    /// Create an expander, by default it follows `.m3u` entries up to 4 levels,
+    /// and children inherit `group-title`
+    pub fn new(fetcher: F) -> Self {
+        Self {
+            fetcher,
+            max_depth: 4,
+            inherited_attributes: vec![attributes::GROUP_TITLE.into()],
+            child_filter: is_m3u_location,
+        }
+    }
+
+    /// Set how many levels of child playlists will be inlined
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// Set the attributes that child entries take from the entry pointing at their playlist
+    pub fn set_inherited_attributes(&mut self, inherited_attributes: Vec<SmolStr>) {
+        self.inherited_attributes = inherited_attributes;
+    }
+
+    /// Set which entries are fetched as child playlists. Children turned out to be
+    /// HLS streams are kept as they are, so it's fine to include `.m3u8` here.
+    pub fn set_child_filter(&mut self, child_filter: fn(&M3uMedia) -> bool) {
+        self.child_filter = child_filter;
+    }
+
+    /// Expand the playlist, relative locations are resolved against `base`
+    pub async fn expand(&mut self, playlist: M3uPlaylist, base: Option<&str>) -> Expanded<E> {
+        let mut failures = Vec::new();
+        let mut result = M3uPlaylist {
+            title: playlist.title,
+            attributes: playlist.attributes,
+            medias: Vec::new(),
+        };
+
+        // depth-first, with an explicit stack so the future doesn't need boxing
+        let mut stack = vec![Frame {
+            location: base.map(SmolStr::new),
+            medias: playlist.medias.into_iter(),
+            base: base.and_then(|x| Url::parse(x).ok()),
+            inherited: HashMap::new(),
+            depth: 0,
+        }];
+
+        while let Some(frame) = stack.last_mut() {
+            let Some(mut media) = frame.medias.next() else {
+                stack.pop();
+                continue;
+            };
+
+            for (key, value) in frame.inherited.iter() {
+                media
+                    .attributes
+                    .entry(key.clone())
+                    .or_insert_with(|| value.clone());
+            }
+
+            if let Some(base) = &frame.base
+                && let Ok(location) = base.join(&media.location)
+            {
+                media.location = location.as_str().into();
+            }
+
+            if frame.depth >= self.max_depth || !(self.child_filter)(&media) {
+                result.medias.push(media);
+                continue;
+            }
+
+            let depth = frame.depth + 1;
+            let mut inherited = frame.inherited.clone();
+            if stack
+                .iter()
+                .any(|x| x.location.as_ref() == Some(&media.location))
+            {
+                failures.push(ExpandFailure {
+                    location: media.location,
+                    error: ExpandError::Cycle,
+                });
+                continue;
+            }
+
+            let content = match (self.fetcher)(media.location.clone()).await {
+                Ok(v) => v,
+                Err(e) => {
+                    failures.push(ExpandFailure {
+                        location: media.location.clone(),
+                        error: ExpandError::Fetch(e),
+                    });
+                    result.medias.push(media);
+                    continue;
+                }
+            };
+
+            let child = match parse_str(&content) {
+                Ok(v) => v.to_owned_playlist(),
+                Err(e) => {
+                    failures.push(ExpandFailure {
+                        location: media.location.clone(),
+                        error: ExpandError::Parse(e),
+                    });
+                    result.medias.push(media);
+                    continue;
+                }
+            };
+
+            // it is a stream, not a list
+            let is_hls = child.medias.iter().any(|x| {
+                HLS_DIRECTIVES
+                    .iter()
+                    .any(|directive| x.extension_data.contains_key(*directive))
+            });
+            if is_hls {
+                result.medias.push(media);
+                continue;
+            }
+
+            for (key, value) in child.attributes {
+                result.attributes.entry(key).or_insert(value);
+            }
+
+            for key in self.inherited_attributes.iter() {
+                if let Some(value) = media.attributes.get(key) {
+                    inherited.insert(key.clone(), value.clone());
+                }
+            }
+
+            stack.push(Frame {
+                base: Url::parse(&media.location).ok(),
+                location: Some(media.location),
+                medias: child.medias.into_iter(),
+                inherited,
+                depth,
+            });
+        }
+
+        Expanded {
+            playlist: result,
+            failures,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use futures::executor::block_on;
+    use smol_str::SmolStr;
+
+    use crate::{ExpandError, Expander, parse_str};
+
+    fn fetch_from(
+        lists: &HashMap<&'static str, &'static str>,
+    ) -> impl FnMut(SmolStr) -> std::future::Ready<Result<String, String>> {
+        move |location| {
+            std::future::ready(
+                lists
+                    .get(location.as_str())
+                    .map(|x| x.to_string())
+                    .ok_or(format!("Not found: {}", location)),
+            )
+        }
+    }
+
+    #[test]
+    fn test_expand_nested() {
+        let lists = HashMap::from([
+            (
+                "http://example.com/a/sports.m3u",
+                "#EXTM3U\n#EXTINF:-1,Match\nmatch.m3u8\n#EXTINF:-1 group-title=\"Football\",More\nmore/football.m3u",
+            ),
+            (
+                "http://example.com/a/more/football.m3u",
+                "#EXTM3U\n#EXTINF:-1,Final\nhttp://cdn.example.com/final.m3u8",
+            ),
+        ]);
+        let root = parse_str(
+            "#EXTM3U\n#EXTINF:-1 group-title=\"Sports\",Sports\na/sports.m3u\n#EXTINF:-1,News\nnews.m3u8",
+        )
+        .unwrap()
+        .to_owned_playlist();
+
+        let mut expander = Expander::new(fetch_from(&lists));
+        let result = block_on(expander.expand(root, Some("http://example.com/")));
+        let medias = &result.playlist.medias;
+
+        assert!(result.failures.is_empty());
+        assert_eq!(medias.len(), 3);
+        assert_eq!(medias[0].location, "http://example.com/a/match.m3u8");
+        assert_eq!(medias[0].attributes.get("group-title").unwrap(), "Sports");
+        assert_eq!(medias[1].location, "http://cdn.example.com/final.m3u8");
+        assert_eq!(medias[1].attributes.get("group-title").unwrap(), "Football");
+        assert_eq!(medias[2].location, "http://example.com/news.m3u8");
+    }
+
+    #[test]
+    fn test_expand_cycle_and_depth() {
+        let lists = HashMap::from([
+            (
+                "http://example.com/a.m3u",
+                "#EXTM3U\n#EXTINF:-1,B\nb.m3u\n#EXTINF:-1,S\ns.ts",
+            ),
+            ("http://example.com/b.m3u", "#EXTM3U\n#EXTINF:-1,A\na.m3u"),
+        ]);
+        let root = parse_str("#EXTM3U\n#EXTINF:-1,A\nhttp://example.com/a.m3u")
+            .unwrap()
+            .to_owned_playlist();
+
+        let mut expander = Expander::new(fetch_from(&lists));
+        let result = block_on(expander.expand(root, None));
+        assert_eq!(result.playlist.medias.len(), 1);
+        assert_eq!(
+            result.playlist.medias[0].location,
+            "http://example.com/s.ts"
+        );
+        assert_eq!(result.failures.len(), 1);
+        assert!(matches!(result.failures[0].error, ExpandError::Cycle));
+
+        let root = parse_str("#EXTM3U\n#EXTINF:-1,A\nhttp://example.com/a.m3u")
+            .unwrap()
+            .to_owned_playlist();
+        let mut expander = Expander::new(fetch_from(&lists));
+        expander.set_max_depth(1);
+        let result = block_on(expander.expand(root, None));
+        assert_eq!(result.playlist.medias.len(), 2);
+        assert_eq!(
+            result.playlist.medias[0].location,
+            "http://example.com/b.m3u"
+        );
+    }
+
+    #[test]
+    fn test_expand_failures_kept() {
+        let lists = HashMap::from([(
+            "http://example.com/live.m3u",
+            "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6,\n0.ts",
+        )]);
+        let root = parse_str("#EXTM3U\nhttp://example.com/live.m3u\nhttp://example.com/gone.m3u")
+            .unwrap()
+            .to_owned_playlist();
+
+        let mut expander = Expander::new(fetch_from(&lists));
+        let result = block_on(expander.expand(root, None));
+        assert_eq!(result.playlist.medias.len(), 2);
+        assert_eq!(
+            result.playlist.medias[0].location,
+            "http://example.com/live.m3u"
+        );
+        assert_eq!(result.failures.len(), 1);
+        assert!(matches!(result.failures[0].error, ExpandError::Fetch(_)));
+    }
+}
@@ -12,3 +12,12 @@ pub const EXTGENRE: &str = "#EXTGENRE";
 pub const EXTATTRFROMURL: &str = "#EXTATTRFROMURL";
 pub const EXTHTTP: &str = "#EXTHTTP";
 pub const KODIPROP: &str = "#KODIPROP";
+
+pub const EXT_X_VERSION: &str = "#EXT-X-VERSION";
+pub const EXT_X_TARGETDURATION: &str = "#EXT-X-TARGETDURATION";
+pub const EXT_X_MEDIA_SEQUENCE: &str = "#EXT-X-MEDIA-SEQUENCE";
+pub const EXT_X_DISCONTINUITY_SEQUENCE: &str = "#EXT-X-DISCONTINUITY-SEQUENCE";
+pub const EXT_X_PLAYLIST_TYPE: &str = "#EXT-X-PLAYLIST-TYPE";
+pub const EXT_X_DISCONTINUITY: &str = "#EXT-X-DISCONTINUITY";
+pub const EXT_X_PROGRAM_DATE_TIME: &str = "#EXT-X-PROGRAM-DATE-TIME";
+pub const EXT_X_ENDLIST: &str = "#EXT-X-ENDLIST";
@@ -1,12 +1,44 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Display};
 
 use smol_str::SmolStr;
 
+/// Duration in `#EXTINF:<duration>`, the original text is kept even if it isn't a number
+#[derive(Clone, Debug, PartialEq)]
+pub struct M3uDuration {
+    /// The original text
+    pub text: SmolStr,
+    /// Seconds parsed from the text, `None` if it is empty or not a number
+    pub seconds: Option<f32>,
+}
+
+impl M3uDuration {
+    pub fn new(text: impl Into<SmolStr>) -> Self {
+        let text = text.into();
+        let seconds = text.trim().parse().ok();
+        Self { text, seconds }
+    }
+}
+
+impl From<f32> for M3uDuration {
+    fn from(value: f32) -> Self {
+        Self {
+            text: SmolStr::new(value.to_string()),
+            seconds: Some(value),
+        }
+    }
+}
+
+impl Display for M3uDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
 pub struct M3uMedia {
     /// Name of this media
     pub name: Option<SmolStr>,
-    /// How long do this media will last
-    pub duration: f32,
+    /// How long do this media will last, `None` if there is no `#EXTINF`
+    pub duration: Option<M3uDuration>,
     /// Location (relative or absolute URL) of this media
     pub location: SmolStr,
     /// Attributes of this media
@@ -19,10 +51,59 @@ impl Default for M3uMedia {
     fn default() -> Self {
         Self {
             name: None,
-            duration: -1.0,
+            duration: None,
             attributes: HashMap::new(),
             extension_data: HashMap::new(),
             location: SmolStr::new(""),
         }
     }
 }
+
+impl M3uMedia {
+    /// Duration in seconds, `None` if it is missing or not a number
+    pub fn get_duration(&self) -> Option<f32> {
+        self.duration.as_ref().and_then(|x| x.seconds)
+    }
+}
+
+/// A borrowed view of a [`M3uMedia`], its fields point into the parsed input
+#[derive(Default)]
+pub struct M3uMediaRef<'a> {
+    /// Name of this media
+    pub name: Option<&'a str>,
+    /// The original duration text, `None` if there is no `#EXTINF`
+    pub duration: Option<&'a str>,
+    /// Location (relative or absolute URL) of this media
+    pub location: &'a str,
+    /// Attributes of this media
+    pub attributes: HashMap<&'a str, &'a str>,
+    /// Directives that not been parsed
+    pub extension_data: HashMap<&'a str, Option<&'a str>>,
+}
+
+impl M3uMediaRef<'_> {
+    /// Duration in seconds, `None` if it is missing or not a number
+    pub fn get_duration(&self) -> Option<f32> {
+        self.duration.and_then(|x| x.trim().parse().ok())
+    }
+}
+
+impl From<&M3uMediaRef<'_>> for M3uMedia {
+    fn from(value: &M3uMediaRef<'_>) -> Self {
+        Self {
+            name: value.name.map(SmolStr::new),
+            duration: value.duration.map(M3uDuration::new),
+            location: SmolStr::new(value.location),
+            attributes: value
+                .attributes
+                .iter()
+                .map(|(k, v)| (SmolStr::new(k), SmolStr::new(v)))
+                .collect(),
+            extension_data: value
+                .extension_data
+                .iter()
+                .map(|(k, v)| (SmolStr::new(k), v.map(SmolStr::new)))
+                .collect(),
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use smol_str::SmolStr;
 use std::collections::HashMap;
 
-use crate::format::M3uMedia;
+use crate::format::{M3uMedia, M3uMediaRef};
 
 #[derive(Default)]
 pub struct M3uPlaylist {
@@ -12,3 +12,41 @@ pub struct M3uPlaylist {
     /// Medias of this playlist
     pub medias: Vec<M3uMedia>,
 }
+
+/// A borrowed view of a [`M3uPlaylist`], produced by [`crate::StrParser`]
+#[derive(Default)]
+pub struct M3uPlaylistRef<'a> {
+    /// Title of this playlist
+    pub title: Option<&'a str>,
+    /// Attributes of this playlist
+    pub attributes: HashMap<&'a str, &'a str>,
+    /// Medias of this playlist
+    pub medias: Vec<M3uMediaRef<'a>>,
+}
+
+impl M3uPlaylistRef<'_> {
+    /// Copy the borrowed view into an owned `M3uPlaylist`
+    pub fn to_owned_playlist(&self) -> M3uPlaylist {
+        M3uPlaylist::from(self)
+    }
+}
+
+impl From<&M3uPlaylistRef<'_>> for M3uPlaylist {
+    fn from(value: &M3uPlaylistRef<'_>) -> Self {
+        Self {
+            title: value.title.map(SmolStr::new),
+            attributes: value
+                .attributes
+                .iter()
+                .map(|(k, v)| (SmolStr::new(k), SmolStr::new(v)))
+                .collect(),
+            medias: value.medias.iter().map(M3uMedia::from).collect(),
+        }
+    }
+}
+
+impl From<M3uPlaylistRef<'_>> for M3uPlaylist {
+    fn from(value: M3uPlaylistRef<'_>) -> Self {
+        Self::from(&value)
+    }
+}
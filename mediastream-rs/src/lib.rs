@@ -20,6 +20,12 @@
 //! ```
 
 mod builder;
+mod expand;
 pub mod format;
+mod live_window;
 mod parser;
+mod str_parser;
+pub use expand::*;
+pub use live_window::*;
 pub use parser::*;
+pub use str_parser::*;
@@ -0,0 +1,330 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt::Display,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use smol_str::SmolStr;
+
+use crate::format::directives;
+
+/// How a [`LiveWindow`] keeps its segments
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowMode {
+    /// Keep the latest N segments, older ones slide out of the playlist
+    Sliding(usize),
+    /// Keep every segment, segments can only be appended (`#EXT-X-PLAYLIST-TYPE:EVENT`)
+    Event,
+    /// Keep every segment, the playlist will not change once finished (`#EXT-X-PLAYLIST-TYPE:VOD`)
+    Vod,
+}
+
+/// A media segment pushed into a [`LiveWindow`]
+#[derive(Clone, Debug)]
+pub struct LiveSegment {
+    /// Location (relative or absolute URL) of this segment
+    pub uri: SmolStr,
+    /// Duration of this segment in seconds
+    pub duration: f32,
+    /// Whether there is a discontinuity between this segment and the previous one
+    pub discontinuity: bool,
+    /// The wall clock time of the first sample of this segment
+    pub program_date_time: Option<SystemTime>,
+}
+
+impl LiveSegment {
+    pub fn new(uri: impl Into<SmolStr>, duration: f32) -> Self {
+        Self {
+            uri: uri.into(),
+            duration,
+            discontinuity: false,
+            program_date_time: None,
+        }
+    }
+}
+
+/// A generator of HLS media playlists, you keep pushing segments into it,
+/// and it keeps the media sequence and discontinuity sequence advancing.
+///
+/// Example:
+/// ```rust
+/// use mediastream_rs::{LiveSegment, LiveWindow, WindowMode};
+///
+/// let mut window = LiveWindow::new(WindowMode::Sliding(3));
+/// for i in 0..5 {
+///     window.push(LiveSegment::new(format!("{}.ts", i), 6.0)).unwrap();
+/// }
+///
+/// let playlist = window.to_string();
+/// assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:2"));
+/// assert!(!playlist.contains("1.ts"));
+///
+/// window.finish();
+/// assert!(window.to_string().ends_with("#EXT-X-ENDLIST\n"));
+/// ```
+pub struct LiveWindow {
+    mode: WindowMode,
+    segments: VecDeque<LiveSegment>,
+    media_sequence: u64,
+    discontinuity_sequence: u64,
+    target_duration: u64,
+    finished: bool,
+}
+
+impl LiveWindow {
+    pub fn new(mode: WindowMode) -> Self {
+        Self {
+            mode,
+            segments: VecDeque::new(),
+            media_sequence: 0,
+            discontinuity_sequence: 0,
+            target_duration: 0,
+            finished: false,
+        }
+    }
+
+    /// Start the sequence numbers from the given values, e.g. when resuming a stream
+    pub fn set_sequences(&mut self, media_sequence: u64, discontinuity_sequence: u64) {
+        self.media_sequence = media_sequence;
+        self.discontinuity_sequence = discontinuity_sequence;
+    }
+
+    /// Set the least `#EXT-X-TARGETDURATION`, it still grows with longer segments
+    pub fn set_target_duration(&mut self, target_duration: u64) {
+        self.target_duration = self.target_duration.max(target_duration);
+    }
+
+    pub fn get_media_sequence(&self) -> u64 {
+        self.media_sequence
+    }
+
+    pub fn get_discontinuity_sequence(&self) -> u64 {
+        self.discontinuity_sequence
+    }
+
+    pub fn get_target_duration(&self) -> u64 {
+        self.target_duration
+    }
+
+    pub fn get_segments(&self) -> &VecDeque<LiveSegment> {
+        &self.segments
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Append a segment, and slide the window if needed
+    pub fn push(&mut self, segment: LiveSegment) -> Result<(), LiveWindowError> {
+        if self.finished {
+            return Err(LiveWindowError::Finished);
+        }
+        if !segment.duration.is_finite() || segment.duration < 0.0 {
+            return Err(LiveWindowError::InvalidDuration);
+        }
+
+        // the target duration must not decrease during the whole stream
+        self.target_duration = self
+            .target_duration
+            .max(segment.duration.round() as u64)
+            .max(1);
+        self.segments.push_back(segment);
+
+        if let WindowMode::Sliding(window_size) = self.mode {
+            while self.segments.len() > window_size.max(1) {
+                let removed = self.segments.pop_front().unwrap();
+                self.media_sequence += 1;
+                if removed.discontinuity {
+                    self.discontinuity_sequence += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Mark the end of the stream, `#EXT-X-ENDLIST` will be appended
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+}
+
+impl Display for LiveWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // header
+        writeln!(f, "{}", directives::EXTM3U)?;
+        writeln!(f, "{}:3", directives::EXT_X_VERSION)?;
+        writeln!(
+            f,
+            "{}:{}",
+            directives::EXT_X_TARGETDURATION,
+            self.target_duration
+        )?;
+        writeln!(
+            f,
+            "{}:{}",
+            directives::EXT_X_MEDIA_SEQUENCE,
+            self.media_sequence
+        )?;
+        if self.discontinuity_sequence != 0 {
+            writeln!(
+                f,
+                "{}:{}",
+                directives::EXT_X_DISCONTINUITY_SEQUENCE,
+                self.discontinuity_sequence
+            )?;
+        }
+        match self.mode {
+            WindowMode::Sliding(_) => {}
+            WindowMode::Event => writeln!(f, "{}:EVENT", directives::EXT_X_PLAYLIST_TYPE)?,
+            WindowMode::Vod => writeln!(f, "{}:VOD", directives::EXT_X_PLAYLIST_TYPE)?,
+        }
+
+        // segments
+        for segment in self.segments.iter() {
+            if segment.discontinuity {
+                writeln!(f, "{}", directives::EXT_X_DISCONTINUITY)?;
+            }
+            if let Some(program_date_time) = segment.program_date_time {
+                writeln!(
+                    f,
+                    "{}:{}",
+                    directives::EXT_X_PROGRAM_DATE_TIME,
+                    format_date_time(program_date_time)
+                )?;
+            }
+            writeln!(f, "{}:{:.3},", directives::EXTINF, segment.duration)?;
+            writeln!(f, "{}", segment.uri)?;
+        }
+
+        if self.finished {
+            writeln!(f, "{}", directives::EXT_X_ENDLIST)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Format as ISO 8601 in UTC, e.g. `2025-01-01T20:00:00.000Z`
+fn format_date_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// From `http://howardhinnant.github.io/date_algorithms.html#civil_from_days`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// Error occurred when pushing into a [`LiveWindow`]
+#[derive(Debug)]
+pub enum LiveWindowError {
+    /// The window has been finished
+    Finished,
+    /// The duration of the segment is negative or not a number
+    InvalidDuration,
+}
+
+impl Display for LiveWindowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Finished => write!(f, "The window has been finished"),
+            Self::InvalidDuration => write!(f, "Invalid segment duration"),
+        }
+    }
+}
+
+impl Error for LiveWindowError {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::{LiveSegment, LiveWindow, LiveWindowError, WindowMode, parse_str};
+
+    #[test]
+    fn test_sliding_window() {
+        let mut window = LiveWindow::new(WindowMode::Sliding(2));
+        let mut discontinuity = LiveSegment::new("1.ts", 4.0);
+        discontinuity.discontinuity = true;
+
+        window.push(LiveSegment::new("0.ts", 6.0)).unwrap();
+        window.push(discontinuity).unwrap();
+        window.push(LiveSegment::new("2.ts", 4.0)).unwrap();
+        assert_eq!(window.get_media_sequence(), 1);
+        assert_eq!(window.get_discontinuity_sequence(), 0);
+        assert_eq!(window.get_target_duration(), 6);
+
+        window.push(LiveSegment::new("3.ts", 4.0)).unwrap();
+        assert_eq!(window.get_media_sequence(), 2);
+        assert_eq!(window.get_discontinuity_sequence(), 1);
+
+        let result = window.to_string();
+        assert!(result.contains("#EXT-X-MEDIA-SEQUENCE:2\n"));
+        assert!(result.contains("#EXT-X-DISCONTINUITY-SEQUENCE:1\n"));
+        assert!(result.contains("#EXT-X-TARGETDURATION:6\n"));
+        assert!(!result.contains("#EXT-X-PLAYLIST-TYPE"));
+
+        let playlist = parse_str(&result).unwrap();
+        assert_eq!(playlist.medias.len(), 2);
+        assert_eq!(playlist.medias[0].location, "2.ts");
+        assert_eq!(playlist.medias[1].get_duration(), Some(4.0));
+    }
+
+    #[test]
+    fn test_event_finish() {
+        let mut window = LiveWindow::new(WindowMode::Event);
+        let mut segment = LiveSegment::new("0.ts", 6.0);
+        segment.program_date_time = Some(UNIX_EPOCH + Duration::from_millis(1735761600250));
+        window.push(segment).unwrap();
+        for i in 1..10 {
+            window
+                .push(LiveSegment::new(format!("{}.ts", i), 6.0))
+                .unwrap();
+        }
+        window.finish();
+
+        let result = window.to_string();
+        assert!(result.contains("#EXT-X-PLAYLIST-TYPE:EVENT\n"));
+        assert!(result.contains("#EXT-X-MEDIA-SEQUENCE:0\n"));
+        assert!(result.contains("#EXT-X-PROGRAM-DATE-TIME:2025-01-01T20:00:00.250Z\n"));
+        assert!(result.ends_with("9.ts\n#EXT-X-ENDLIST\n"));
+        assert!(matches!(
+            window.push(LiveSegment::new("10.ts", 6.0)),
+            Err(LiveWindowError::Finished)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_duration() {
+        let mut window = LiveWindow::new(WindowMode::Vod);
+        assert!(matches!(
+            window.push(LiveSegment::new("0.ts", f32::NAN)),
+            Err(LiveWindowError::InvalidDuration)
+        ));
+    }
+}
@@ -10,23 +10,101 @@ use lazy_static::lazy_static;
 use regex::Regex;
 use smol_str::SmolStr;
 
-use crate::format::{M3uMedia, M3uPlaylist, directives};
+use crate::format::{M3uDuration, M3uMedia, M3uPlaylist, directives};
 
 lazy_static! {
     /// From `https://github.com/Raiper34/m3u-parser-generator/blob/c8e479161dcc4ec3d5490631fa42a1647741481d/src/m3u-parser.ts#L52` (Modified)
     static ref ATTRIBUTE_REGEX: Regex = Regex::new("([^ ]*?)=\"(.*?)\"").expect("Regular expression error");
 }
 
+pub(crate) fn attribute_pairs(input: &str) -> impl Iterator<Item = (&str, &str)> {
+    ATTRIBUTE_REGEX.captures_iter(input).map(|x| {
+        let (_, [key, value]) = x.extract();
+        (key, value)
+    })
+}
+
 fn parse_attributes(input: impl AsRef<str>) -> HashMap<SmolStr, SmolStr> {
-    let mut result = HashMap::new();
-    for (_, [key, value]) in ATTRIBUTE_REGEX
-        .captures_iter(input.as_ref())
-        .map(|x| x.extract())
+    attribute_pairs(input.as_ref())
+        .map(|(key, value)| (key.into(), value.into()))
+        .collect()
+}
+
+/// The pieces of an `#EXTINF:<duration> <attributes>,<name>` directive value
+pub(crate) struct MediaInfo<'a> {
+    pub duration: &'a str,
+    pub attributes: Option<&'a str>,
+    pub name: Option<&'a str>,
+}
+
+pub(crate) fn split_media_info(value: &str) -> MediaInfo<'_> {
+    let mut splited_value = value.split(',');
+    // parse duration with attributes
+    let maybe_duration = splited_value.next().unwrap_or_default();
+
+    // parse title
+    let name = splited_value.next();
+
+    // parse duration, keep it as it is, even if it is empty or not a number
+    let mut splited_duration = maybe_duration.splitn(2, ' ');
+    let duration = splited_duration.next().unwrap_or_default();
+
+    MediaInfo {
+        duration,
+        attributes: splited_duration.next(),
+        name,
+    }
+}
+
+pub(crate) fn split_directive(line: &str) -> (&str, Option<&str>) {
+    let mut splited_line = line.splitn(2, ':');
+    let key = splited_line.next().unwrap();
+    (key, splited_line.next())
+}
+
+/// Check the first line, return the attributes part if it is a `#EXTM3U` header,
+/// or `None` if it is the first entry of a playlist without header
+pub(crate) fn split_m3u_header(first_line: &str) -> Result<Option<&str>, ParseError> {
+    if first_line.starts_with(directives::EXTM3U) {
+        return Ok(Some(first_line[directives::EXTM3U_LEN..].trim_start()));
+    }
+
+    // a headerless playlist should start with a directive or a location,
+    // reject things like HTML or JSON error pages
+    if first_line.starts_with('#') || is_location(first_line) {
+        return Ok(None);
+    }
+
+    Err(ParseError::NotAPlaylist)
+}
+
+/// Whether the line has the shape of a URL or a path, which may contain spaces,
+/// a relative path needs a file extension
+fn is_location(line: &str) -> bool {
+    let line = line.trim();
+    if line.starts_with(['<', '{', '[']) {
+        return false;
+    }
+
+    if let Some((scheme, _)) = line.split_once("://")
+        && !scheme.is_empty()
+        && scheme
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || matches!(x, '+' | '-' | '.'))
     {
-        result.insert(key.into(), value.into());
+        return true;
+    }
+    if line.starts_with(['/', '\\']) || line.starts_with("./") || line.starts_with("../") {
+        return true;
     }
 
-    result
+    let path = line.split(['?', '#']).next().unwrap_or_default();
+    let name = path.rsplit(['/', '\\']).next().unwrap_or_default();
+    name.rsplit_once('.').is_some_and(|(stem, extension)| {
+        !stem.is_empty()
+            && !extension.is_empty()
+            && extension.chars().all(|x| x.is_ascii_alphanumeric())
+    })
 }
 
 /// A parser to parse M3U/M3U8 file.
@@ -89,16 +167,7 @@ impl<T: BufRead> Parser<T> {
         self.parse_m3u_header()?;
 
         while let Some(line) = self.next_line()? {
-            if line.starts_with('#') {
-                // directive
-                self.parse_directive(line)?;
-            } else {
-                // media
-                self.media.location = SmolStr::new(line);
-                let mut media = M3uMedia::default();
-                swap(&mut self.media, &mut media);
-                self.playlist.medias.push(media);
-            }
+            self.parse_line(line);
         }
 
         Ok(())
@@ -151,7 +220,7 @@ impl<T: BufRead> Parser<T> {
                 Err(e) => return Err(e),
             }
 
-            if self.buffer.trim().len() != 0 {
+            if !self.buffer.trim().is_empty() {
                 return Ok(Some(self.buffer.trim().to_owned()));
             }
         }
@@ -160,70 +229,62 @@ impl<T: BufRead> Parser<T> {
     fn parse_m3u_header(&mut self) -> Result<(), ParseError> {
         let first_line = self.next_line()?.ok_or(ParseError::UnexpectedEOF)?;
 
-        if !first_line.starts_with(directives::EXTM3U) {
-            return Err(ParseError::NotAPlaylist);
+        match split_m3u_header(&first_line)? {
+            Some(attributes) => self
+                .playlist
+                .attributes
+                .extend(parse_attributes(attributes)),
+            None => self.parse_line(first_line),
         }
 
-        let attributes = first_line
-            .chars()
-            .skip(directives::EXTM3U_LEN)
-            .skip_while(|x| x.is_whitespace())
-            .collect::<String>();
-
-        let attributes = parse_attributes(attributes);
-        self.playlist.attributes.extend(attributes);
-
         Ok(())
     }
 
-    fn parse_media_info(&mut self, value: SmolStr) -> Result<(), ParseError> {
-        let mut splited_value = value.split(',');
-        // parse duration with attributes
-        let maybe_duration = splited_value.next().ok_or(ParseError::MissingDuration)?;
-
-        // parse title
-        let title = splited_value.next();
+    fn parse_line(&mut self, line: String) {
+        if line.starts_with('#') {
+            // directive
+            self.parse_directive(line);
+        } else {
+            // media
+            self.media.location = SmolStr::new(line);
+            let mut media = M3uMedia::default();
+            swap(&mut self.media, &mut media);
+            self.playlist.medias.push(media);
+        }
+    }
 
-        // parse name
-        self.media.name = title.map(|x| x.into());
-        let mut splited_duration = maybe_duration.splitn(2, ' ');
+    fn parse_media_info(&mut self, value: &str) {
+        let info = split_media_info(value);
 
-        // parse duration
-        let duration = splited_duration.next().ok_or(ParseError::MissingDuration)?;
-        self.media.duration = duration.parse().map_err(|_| ParseError::MissingDuration)?;
+        self.media.name = info.name.map(|x| x.into());
+        self.media.duration = Some(M3uDuration::new(info.duration));
 
         // parse attribute
-        if let Some(attributes) = splited_duration.next() {
+        if let Some(attributes) = info.attributes {
             self.media.attributes.extend(parse_attributes(attributes));
         }
-
-        Ok(())
     }
 
-    fn parse_directive(&mut self, line: String) -> Result<(), ParseError> {
-        let mut splited_line = line.splitn(2, ':');
-        let key = splited_line.next().unwrap().into();
-        let value = splited_line.next().map(|x| x.into());
+    fn parse_directive(&mut self, line: String) {
+        let (key, value) = split_directive(&line);
 
         if key == directives::EXTINF {
-            self.parse_media_info(value.unwrap_or_default())?;
+            self.parse_media_info(value.unwrap_or_default());
         } else if key == directives::PLAYLIST {
-            self.playlist.title = Some(value.unwrap_or_default());
+            self.playlist.title = Some(value.unwrap_or_default().into());
         } else {
-            self.media.extension_data.insert(key, value);
+            self.media
+                .extension_data
+                .insert(key.into(), value.map(|x| x.into()));
         }
-
-        Ok(())
     }
 }
 
 /// Error occurred during parsing
 #[derive(Debug)]
 pub enum ParseError {
-    /// File doesn't start with `#EXTM3U`
+    /// File doesn't start with `#EXTM3U`, and doesn't look like a playlist without header
     NotAPlaylist,
-    /// `#EXTINF:<duration>`, the duration is missing
-    MissingDuration,
     /// Unexpected EOF while parsing
     UnexpectedEOF,
     // IO error
@@ -236,7 +297,6 @@ impl Display for ParseError {
             Self::NotAPlaylist => write!(f, "Not a playlist file"),
             Self::IoError(e) => e.fmt(f),
             Self::UnexpectedEOF => write!(f, "Unexpected EOF"),
-            Self::MissingDuration => write!(f, "Duration of a media is missing"),
         }
     }
 }
@@ -251,7 +311,7 @@ impl From<io::Error> for ParseError {
 mod tests {
     use std::io::Cursor;
 
-    use crate::{Parser, parser::parse_attributes};
+    use crate::{ParseError, Parser, parser::parse_attributes};
 
     #[test]
     fn test_parse_attributes() {
@@ -321,4 +381,68 @@ http://example.com/D.m3u8
             "http://example.com/D.m3u8"
         );
     }
+
+    #[test]
+    fn test_parse_lenient_duration() {
+        let data = r#"#EXTM3U
+#EXTINF:,Channel A
+http://example.com/A.m3u8
+#EXTINF:-1.0.0 tvg-id="b",Channel B
+http://example.com/B.m3u8
+#EXTINF:-1,Channel C
+http://example.com/C.m3u8"#;
+        let mut parser = Parser::new(Cursor::new(data));
+        parser.parse().unwrap();
+        let result = parser.get_playlist();
+
+        assert_eq!(result.medias.len(), 3);
+        assert_eq!(result.medias[0].name.as_ref().unwrap(), "Channel A");
+        assert_eq!(result.medias[0].get_duration(), None);
+        assert_eq!(result.medias[1].get_duration(), None);
+        assert_eq!(result.medias[1].attributes.get("tvg-id").unwrap(), "b");
+        assert_eq!(result.medias[2].get_duration(), Some(-1.0));
+
+        // the original text is written back
+        let output = result.to_string();
+        assert!(output.contains("#EXTINF:,Channel A\n"));
+        assert!(output.contains("#EXTINF:-1.0.0 tvg-id=\"b\",Channel B\n"));
+    }
+
+    #[test]
+    fn test_parse_headerless() {
+        let data = "
+http://example.com/A.m3u8
+http://example.com/B.m3u8
+";
+        let mut parser = Parser::new(Cursor::new(data));
+        parser.parse().unwrap();
+        let result = parser.get_playlist();
+
+        assert_eq!(result.medias.len(), 2);
+        assert_eq!(result.medias[0].location, "http://example.com/A.m3u8");
+        assert!(result.medias[0].duration.is_none());
+        assert!(
+            result
+                .to_string()
+                .contains("#EXTINF:-1,\nhttp://example.com/B.m3u8")
+        );
+
+        let mut parser = Parser::new(Cursor::new("#EXTINF:-1,A\nA.ts"));
+        parser.parse().unwrap();
+        assert_eq!(parser.get_playlist().medias.len(), 1);
+
+        // paths with spaces
+        let mut parser = Parser::new(Cursor::new("My Show/Episode 1.mp4\n/media/Episode 2.mp4"));
+        parser.parse().unwrap();
+        let result = parser.get_playlist();
+        assert_eq!(result.medias.len(), 2);
+        assert_eq!(result.medias[0].location, "My Show/Episode 1.mp4");
+
+        let mut parser = Parser::new(Cursor::new("<html>\n<body>Not found</body>"));
+        assert!(matches!(parser.parse(), Err(ParseError::NotAPlaylist)));
+        let mut parser = Parser::new(Cursor::new("404 Not Found"));
+        assert!(matches!(parser.parse(), Err(ParseError::NotAPlaylist)));
+        let mut parser = Parser::new(Cursor::new("{\"error\": \"a.b\"}"));
+        assert!(matches!(parser.parse(), Err(ParseError::NotAPlaylist)));
+    }
 }
@@ -0,0 +1,178 @@
+use std::mem::take;
+
+use crate::{
+    ParseError,
+    format::{M3uMediaRef, M3uPlaylistRef, directives},
+    parser::{attribute_pairs, split_directive, split_m3u_header, split_media_info},
+};
+
+/// A parser to parse M3U/M3U8 content that is already in memory.
+///
+/// Unlike [`crate::Parser`], it doesn't copy anything, the result borrows from the input.
+///
+/// Example:
+/// ```rust
+/// use mediastream_rs::StrParser;
+///
+/// let mut parser = StrParser::new(r#"
+/// #EXTM3U x-tvg-url="test"
+/// #EXTINF:1 tvg-id="a" provider-type="iptv",A
+/// http://example.com/A.m3u8"#);
+/// parser.parse().unwrap();
+/// let result = parser.get_playlist();
+/// assert_eq!(result.medias[0].location, "http://example.com/A.m3u8");
+///
+/// // Convert to an owned playlist when needed
+/// let _owned = result.to_owned_playlist();
+/// ```
+pub struct StrParser<'a> {
+    lines: std::str::Lines<'a>,
+    playlist: M3uPlaylistRef<'a>,
+    media: M3uMediaRef<'a>,
+}
+
+impl<'a> StrParser<'a> {
+    /// Create a parser from a string
+    pub fn new(input: &'a str) -> Self {
+        Self {
+            lines: input.lines(),
+            playlist: M3uPlaylistRef::default(),
+            media: M3uMediaRef::default(),
+        }
+    }
+
+    /// Parse the content until the end, and return the error if occurred
+    pub fn parse(&mut self) -> Result<(), ParseError> {
+        self.parse_m3u_header()?;
+
+        while let Some(line) = self.next_line() {
+            self.parse_line(line);
+        }
+
+        Ok(())
+    }
+
+    /// Get the parsed `M3uPlaylistRef`
+    pub fn get_playlist(&mut self) -> M3uPlaylistRef<'a> {
+        take(&mut self.playlist)
+    }
+
+    fn next_line(&mut self) -> Option<&'a str> {
+        self.lines.by_ref().map(str::trim).find(|x| !x.is_empty())
+    }
+
+    fn parse_m3u_header(&mut self) -> Result<(), ParseError> {
+        let first_line = self.next_line().ok_or(ParseError::UnexpectedEOF)?;
+
+        match split_m3u_header(first_line)? {
+            Some(attributes) => self.playlist.attributes.extend(attribute_pairs(attributes)),
+            None => self.parse_line(first_line),
+        }
+
+        Ok(())
+    }
+
+    fn parse_line(&mut self, line: &'a str) {
+        if line.starts_with('#') {
+            // directive
+            self.parse_directive(line);
+        } else {
+            // media
+            self.media.location = line;
+            let media = take(&mut self.media);
+            self.playlist.medias.push(media);
+        }
+    }
+
+    fn parse_media_info(&mut self, value: &'a str) {
+        let info = split_media_info(value);
+
+        self.media.name = info.name;
+        self.media.duration = Some(info.duration);
+
+        // parse attribute
+        if let Some(attributes) = info.attributes {
+            self.media.attributes.extend(attribute_pairs(attributes));
+        }
+    }
+
+    fn parse_directive(&mut self, line: &'a str) {
+        let (key, value) = split_directive(line);
+
+        if key == directives::EXTINF {
+            self.parse_media_info(value.unwrap_or_default());
+        } else if key == directives::PLAYLIST {
+            self.playlist.title = Some(value.unwrap_or_default());
+        } else {
+            self.media.extension_data.insert(key, value);
+        }
+    }
+}
+
+/// Parse M3U/M3U8 content in memory, the result borrows from the input
+///
+/// Example:
+/// ```rust
+/// use mediastream_rs::parse_str;
+///
+/// let result = parse_str("#EXTM3U\n#EXTINF:1,A\nA.ts").unwrap();
+/// assert_eq!(result.medias[0].name, Some("A"));
+/// ```
+pub fn parse_str(input: &str) -> Result<M3uPlaylistRef<'_>, ParseError> {
+    let mut parser = StrParser::new(input);
+    parser.parse()?;
+    Ok(parser.get_playlist())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::{Parser, parse_str};
+
+    const DATA: &str = r#"
+#EXTM3U x-tvg-url="test"
+#PLAYLIST:My list
+
+#EXTINF:1 tvg-id="a" provider-type="iptv",A
+http://example.com/A.m3u8
+
+#EXTVLCOPT:http-user-agent=Foo
+#EXTINF:2 tvg-id="b" provider-type="iptv",B
+http://example.com/B.m3u8
+"#;
+
+    #[test]
+    fn test_parse_str() {
+        let result = parse_str(DATA).unwrap();
+
+        assert_eq!(result.title, Some("My list"));
+        assert_eq!(result.attributes.get("x-tvg-url"), Some(&"test"));
+        assert_eq!(result.medias.len(), 2);
+        assert_eq!(result.medias[1].name, Some("B"));
+        assert_eq!(result.medias[1].get_duration(), Some(2.0));
+        assert_eq!(
+            result.medias[1].extension_data.get("#EXTVLCOPT"),
+            Some(&Some("http-user-agent=Foo"))
+        );
+    }
+
+    #[test]
+    fn test_same_as_owned_parser() {
+        let mut parser = Parser::new(Cursor::new(DATA));
+        parser.parse().unwrap();
+        let owned = parser.get_playlist();
+        let converted = parse_str(DATA).unwrap().to_owned_playlist();
+
+        assert_eq!(owned.title, converted.title);
+        assert_eq!(owned.attributes, converted.attributes);
+        assert_eq!(owned.medias.len(), converted.medias.len());
+        for (a, b) in owned.medias.iter().zip(converted.medias.iter()) {
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.duration, b.duration);
+            assert_eq!(a.location, b.location);
+            assert_eq!(a.attributes, b.attributes);
+            assert_eq!(a.extension_data, b.extension_data);
+        }
+    }
+}
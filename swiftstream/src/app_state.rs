@@ -1,11 +1,15 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
+use log::error;
 use reqwest::{Client, Proxy};
 use typed_container::Container;
 
 use crate::{
-    Config,
-    caching::{CachePool, Downloader, StreamTrackingPool},
+    Config, Metrics,
+    caching::{
+        CachePolicies, CachePool, DiskCache, Downloader, PinningScheduler, StaleStore,
+        StreamTrackingPool,
+    },
     transfer::ProxyManager,
 };
 
@@ -14,7 +18,10 @@ pub struct AppState {
     pub config: Arc<Config>,
     pub cache_pool: Arc<CachePool>,
     pub tracking_pool: Arc<StreamTrackingPool>,
+    /// The last good media playlists, served if refreshing them fails
+    pub playlists: Arc<StaleStore>,
     pub http_client: Client,
+    pub metrics: Arc<Metrics>,
 }
 
 impl AppState {
@@ -22,6 +29,8 @@ impl AppState {
         let config = Arc::new(config);
         let container = Container::new();
 
+        container.register_constructor(|_| Metrics::new());
+
         container.register_constructor(|_| {
             let mut builder = Client::builder();
 
@@ -46,13 +55,43 @@ impl AppState {
             Arc::new(Downloader::new(
                 x.get(),
                 config.download_threads.unwrap_or(1), // default single thread
+                config.failure.clone(),
             ))
         });
 
+        container.register_constructor(|_| {
+            config.disk_cache.as_ref().and_then(|disk_cache| {
+                DiskCache::open(
+                    &disk_cache.path,
+                    disk_cache.size_limit.unwrap_or(4 * 1024 * 1024 * 1024), // 4GB
+                )
+                .inspect_err(|e| {
+                    error!(
+                        "Failed to open disk cache {}, caching in memory only: {}",
+                        disk_cache.path, e
+                    )
+                })
+                .ok()
+            })
+        });
+
         container.register_constructor(|x| {
             CachePool::new(
                 config.size_limit.unwrap_or(512 * 1024 * 1024), // 512MB
                 config.cache_expire.unwrap_or(30),              // 30s
+                CachePolicies {
+                    eviction: config.eviction_policy,
+                    failure: config.failure.clone(),
+                    quota: config.quota.clone(),
+                    keys: config.cache_key.clone(),
+                    dedupe: config.dedupe,
+                    validation: config.validation.clone(),
+                    sniffing: config.sniffing.clone(),
+                    ttl: config.ttl.clone(),
+                },
+                config.cache_store.create(),
+                x.get(),
+                x.get(),
                 x.get(),
             )
         });
@@ -63,14 +102,37 @@ impl AppState {
                 config.track_interval.unwrap_or(8), // 8s
                 x.get(),
                 x.get(),
+                x.get(),
             )
         });
 
+        container.register_constructor(|_| {
+            Arc::new(StaleStore::new(
+                Duration::from_secs(config.failure.stale_if_error),
+                None,
+                None,
+            ))
+        });
+
+        // keep the pinned channels warm
+        if let Some(pinning) = &config.pinning {
+            PinningScheduler::new(
+                pinning.clone(),
+                config.track_interval.unwrap_or(8),
+                container.get(),
+                container.get(),
+                container.get(),
+            )
+            .start();
+        }
+
         Self {
             config: config.clone(),
             cache_pool: container.get(),
             tracking_pool: container.get(),
+            playlists: container.get(),
             http_client: container.get(),
+            metrics: container.get(),
         }
     }
 }
@@ -0,0 +1,175 @@
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use url::Url;
+
+/// How a segment URL is turned into the key it is cached by,
+/// the URL itself is still used to download the segment
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct KeyRules {
+    pub ignore_fragment: bool,
+    /// Lowercase the whole key, the scheme and host are always case-insensitive
+    pub ignore_case: bool,
+    pub hosts: Vec<HostKeyRule>,
+}
+
+/// Applied to the URLs whose host matches
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostKeyRule {
+    /// A host name, `*.example.com` for its subdomains, or `*` for all hosts
+    pub host: String,
+    /// Query parameters removed from the key
+    #[serde(default)]
+    pub strip_query: Vec<String>,
+    /// If set, only these query parameters are kept in the key
+    pub keep_query: Option<Vec<String>>,
+    /// Applied to the key in order, after the query parameters are filtered
+    #[serde(default)]
+    pub rewrites: Vec<KeyRewrite>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct KeyRewrite {
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub pattern: Regex,
+    /// May refer to the groups of the pattern, like `$1` or `${name}`
+    pub replace: String,
+}
+
+fn deserialize_regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(serde::de::Error::custom)
+}
+
+impl Default for KeyRules {
+    fn default() -> Self {
+        Self {
+            ignore_fragment: true,
+            ignore_case: false,
+            hosts: Vec::new(),
+        }
+    }
+}
+
+/// Whether `host` matches a host name, `*.example.com` (its subdomains), or `*` (all hosts)
+pub fn matches_host(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*") {
+        Some("") => true,
+        Some(suffix) => host.ends_with(suffix),
+        None => pattern.eq_ignore_ascii_case(host),
+    }
+}
+
+impl HostKeyRule {
+    fn matches(&self, host: &str) -> bool {
+        matches_host(&self.host, host)
+    }
+
+    fn keeps_query(&self, name: &str) -> bool {
+        if self.strip_query.iter().any(|x| x == name) {
+            return false;
+        }
+        self.keep_query
+            .as_ref()
+            .is_none_or(|keep| keep.iter().any(|x| x == name))
+    }
+}
+
+impl KeyRules {
+    /// The key of a URL, the URL is used as is if it can't be parsed
+    pub fn get_key(&self, origin: &str) -> String {
+        let Ok(mut url) = Url::parse(origin) else {
+            return origin.to_owned();
+        };
+
+        if self.ignore_fragment {
+            url.set_fragment(None);
+        }
+
+        let host = url.host_str().unwrap_or_default().to_owned();
+        let rules = self
+            .hosts
+            .iter()
+            .filter(|x| x.matches(&host))
+            .collect::<Vec<_>>();
+
+        if url.query().is_some() && !rules.is_empty() {
+            let pairs = url
+                .query_pairs()
+                .filter(|(name, _)| rules.iter().all(|x| x.keeps_query(name)))
+                .map(|(name, value)| (name.into_owned(), value.into_owned()))
+                .collect::<Vec<_>>();
+
+            if pairs.is_empty() {
+                url.set_query(None);
+            } else {
+                url.query_pairs_mut().clear().extend_pairs(pairs);
+            }
+        }
+
+        let mut key = url.to_string();
+        if self.ignore_case {
+            key = key.to_lowercase();
+        }
+
+        for rewrite in rules.iter().flat_map(|x| x.rewrites.iter()) {
+            key = rewrite
+                .pattern
+                .replace_all(&key, rewrite.replace.as_str())
+                .into_owned();
+        }
+
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use crate::caching::{HostKeyRule, KeyRewrite, KeyRules};
+
+    #[test]
+    fn test_key() {
+        let rules = KeyRules {
+            ignore_fragment: true,
+            ignore_case: true,
+            hosts: vec![
+                HostKeyRule {
+                    host: "*.example.com".into(),
+                    strip_query: vec!["token".into()],
+                    keep_query: None,
+                    rewrites: vec![KeyRewrite {
+                        pattern: Regex::new(r"/session-\w+/").unwrap(),
+                        replace: "/".into(),
+                    }],
+                },
+                HostKeyRule {
+                    host: "live.example.org".into(),
+                    strip_query: Vec::new(),
+                    keep_query: Some(vec!["quality".into()]),
+                    rewrites: Vec::new(),
+                },
+            ],
+        };
+
+        assert_eq!(
+            rules.get_key("http://cdn.example.com/session-ab12/Seg0.ts?token=x&v=1#t=3"),
+            "http://cdn.example.com/seg0.ts?v=1"
+        );
+        assert_eq!(
+            rules.get_key("http://cdn.example.com/seg0.ts?token=y"),
+            "http://cdn.example.com/seg0.ts"
+        );
+        assert_eq!(
+            rules.get_key("http://live.example.org/0.ts?quality=hd&expires=123&sig=abc"),
+            "http://live.example.org/0.ts?quality=hd"
+        );
+        // other hosts keep their query parameters
+        assert_eq!(
+            rules.get_key("http://example.net/0.ts?token=z"),
+            "http://example.net/0.ts?token=z"
+        );
+    }
+}
@@ -1,60 +1,278 @@
+use bytes::Bytes;
+use futures::join;
 use log::{debug, error, warn};
 
+use serde::Serialize;
 use std::{
     collections::HashMap,
-    sync::Arc,
-    time::{Duration, SystemTime},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tokio::{io, sync::RwLock, task::yield_now, time::sleep};
+use tokio::{
+    io,
+    sync::RwLock,
+    time::{sleep, timeout},
+};
+
+use url::Url;
 
-use crate::caching::{DownloadError, Downloader};
+use crate::{
+    Metrics,
+    caching::{
+        AccessStats, CacheStore, ContentStats, ContentStore, DiskCache, DownloadError, Downloader,
+        EvictionPolicy, FailurePolicy, KeyRules, MemoryBudget, QuotaPolicy, SegmentValidator,
+        SegmentView, SharedBuffer, SniffingConfig, StaleStore, TtlPolicy, ValidationConfig,
+    },
+};
 
 pub struct CachePool {
-    cached: RwLock<HashMap<String, Arc<CacheItem>>>,
+    store: Box<dyn CacheStore>,
     time_limit_secs: u16,
-    size_limit: usize,
+    budget: Arc<MemoryBudget>,
     downloader: Arc<Downloader>,
+    disk_cache: Option<Arc<DiskCache>>,
+    metrics: Arc<Metrics>,
+    eviction_policy: EvictionPolicy,
+    failure_policy: FailurePolicy,
+    quota_policy: Option<QuotaPolicy>,
+    key_rules: KeyRules,
+    validators: Vec<Box<dyn SegmentValidator>>,
+    sniffing: Option<SniffingConfig>,
+    ttl_policy: TtlPolicy,
+    /// Expired segments, served if reloading them fails
+    stale: StaleStore,
+    /// Shares identical payloads, if dedupe is enabled
+    content_store: Option<Arc<ContentStore>>,
+    /// The budget of each quota group having items
+    groups: Mutex<HashMap<String, Arc<MemoryBudget>>>,
+    /// The GDSF clock, it is the priority of the last evicted item
+    clock: Mutex<f64>,
+    /// The streams whose segments are not evicted, until when
+    pinned: Mutex<HashMap<String, Instant>>,
+}
+
+/// How the pool keys, stores, keeps and retries items
+#[derive(Clone, Debug, Default)]
+pub struct CachePolicies {
+    pub eviction: EvictionPolicy,
+    pub failure: FailurePolicy,
+    pub quota: Option<QuotaPolicy>,
+    pub keys: KeyRules,
+    /// Share one copy of the segments with identical bytes
+    pub dedupe: bool,
+    pub validation: ValidationConfig,
+    /// Strip what is before the real payload, `None` to keep segments verbatim
+    pub sniffing: Option<SniffingConfig>,
+    pub ttl: TtlPolicy,
 }
 
 impl CachePool {
-    pub fn new(size_limit: usize, time_limit_secs: u16, downloader: Arc<Downloader>) -> Arc<Self> {
+    pub fn new(
+        size_limit: usize,
+        time_limit_secs: u16,
+        policies: CachePolicies,
+        store: Box<dyn CacheStore>,
+        downloader: Arc<Downloader>,
+        disk_cache: Option<Arc<DiskCache>>,
+        metrics: Arc<Metrics>,
+    ) -> Arc<Self> {
+        let budget = Arc::new(MemoryBudget::new(size_limit));
+        let content_store = policies
+            .dedupe
+            .then(|| Arc::new(ContentStore::new(budget.clone())));
         Arc::new(CachePool {
-            cached: RwLock::new(HashMap::new()),
-            size_limit,
+            store,
+            stale: StaleStore::new(
+                Duration::from_secs(policies.failure.stale_if_error),
+                Some(budget.clone()),
+                content_store.clone(),
+            ),
+            content_store,
+            budget,
             time_limit_secs,
             downloader,
+            disk_cache,
+            metrics,
+            eviction_policy: policies.eviction,
+            failure_policy: policies.failure,
+            quota_policy: policies.quota,
+            key_rules: policies.keys,
+            validators: policies.validation.create(),
+            sniffing: policies.sniffing,
+            ttl_policy: policies.ttl,
+            groups: Mutex::new(HashMap::new()),
+            clock: Mutex::new(0.0),
+            pinned: Mutex::new(HashMap::new()),
         })
     }
 
-    pub async fn prepare(self: &Arc<Self>, origin: impl AsRef<str>) {
+    /// Start downloading a segment before it is requested,
+    /// `stream` is the playlist it belongs to, used to group it for quotas
+    pub async fn prepare(self: &Arc<Self>, origin: impl AsRef<str>, stream: Option<&str>) {
         let origin = origin.as_ref().to_owned();
+        let stream = stream.map(str::to_owned);
         let self_arc = self.clone();
         tokio::spawn(async move {
-            self_arc.get_internal(origin).await;
+            if let Some(disk_cache) = &self_arc.disk_cache
+                && disk_cache.contains(self_arc.get_key(&origin)).await
+            {
+                return;
+            }
+            self_arc.get_internal(origin, stream.as_deref()).await;
         });
     }
 
-    async fn get_total_size(&self) -> usize {
-        self.cached
-            .read()
-            .await
+    /// Keep the segments of `stream` from eviction for at least `duration` from now
+    pub fn pin_for(&self, stream: &str, duration: Duration) {
+        let now = Instant::now();
+        let until = now + duration;
+        let mut pinned = self.pinned.lock().unwrap();
+        pinned.retain(|_, x| *x > now);
+        let entry = pinned.entry(stream.to_owned()).or_insert(until);
+        *entry = (*entry).max(until);
+    }
+
+    fn is_pinned(&self, item: &CacheItem) -> bool {
+        let Some(stream) = item.get_stream() else {
+            return false;
+        };
+        self.pinned
+            .lock()
+            .unwrap()
+            .get(&stream)
+            .is_some_and(|x| *x > Instant::now())
+    }
+
+    /// The key a segment is cached by
+    pub fn get_key(&self, origin: &str) -> String {
+        self.key_rules.get_key(origin)
+    }
+
+    /// Count of the items in memory, and the bytes reserved by them
+    pub async fn get_stats(&self) -> (usize, usize) {
+        (self.store.len(), self.budget.get_used())
+    }
+
+    /// `None` if dedupe is disabled
+    pub fn get_content_stats(&self) -> Option<ContentStats> {
+        self.content_store.as_ref().map(|x| x.get_stats())
+    }
+
+    /// Describe all items in memory
+    pub async fn list(&self) -> Vec<CacheItemInfo> {
+        let items = self.store.items();
+
+        let mut result = Vec::with_capacity(items.len());
+        for item in items {
+            let state = if item.buffer.is_finished() {
+                CacheItemState::Ready
+            } else if item.buffer.is_failed() {
+                CacheItemState::Failed
+            } else {
+                CacheItemState::Loading
+            };
+
+            result.push(CacheItemInfo {
+                origin: item.origin.clone(),
+                key: item.key.clone(),
+                group: item.group.as_ref().map(|x| x.name.clone()),
+                size: item.get_size(),
+                state,
+                expire: item
+                    .expire
+                    .read()
+                    .await
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+                ttl: item.get_ttl().map(|x| x.as_secs()),
+            });
+        }
+
+        result
+    }
+
+    /// Remove the items whose key matches, from memory and disk, returns how many are removed
+    pub async fn purge(&self, matches: impl Fn(&str) -> bool) -> usize {
+        let purged = self
+            .store
+            .items()
             .iter()
-            .map(|x| x.1.get_size())
-            .sum()
+            .filter(|x| matches(&x.key))
+            .filter_map(|x| self.store.remove(&x.key))
+            .collect::<Vec<_>>();
+
+        for item in purged.iter() {
+            item.buffer.fail(UNAVAILABLE);
+            debug!("Resource {} purged", item.origin);
+        }
+        self.stale.purge(&matches);
+
+        let disk_purged = match &self.disk_cache {
+            Some(disk_cache) => disk_cache.purge(&matches).await,
+            None => 0,
+        };
+
+        purged.len() + disk_purged
     }
 
-    async fn get_internal(self: &Arc<Self>, origin: String) -> Option<Arc<CacheItem>> {
-        if let Some(item_ref) = self.cached.read().await.get(&origin) {
-            return Some(item_ref.clone());
+    /// The quota group of a segment, `None` if quotas are disabled
+    fn get_group(&self, origin: &str, stream: Option<&str>) -> Option<CacheGroup> {
+        let quota_policy = self.quota_policy.as_ref()?;
+        let name = quota_policy.get_group(origin, stream);
+
+        let mut groups = self.groups.lock().unwrap();
+        // forget the groups no buffer is accounted in
+        groups.retain(|_, x| Arc::strong_count(x) > 1);
+        let budget = groups
+            .entry(name.clone())
+            .or_insert_with(|| {
+                Arc::new(MemoryBudget::new(
+                    quota_policy.max_size.unwrap_or(usize::MAX),
+                ))
+            })
+            .clone();
+
+        Some(CacheGroup { name, budget })
+    }
+
+    async fn get_internal(
+        self: &Arc<Self>,
+        origin: String,
+        stream: Option<&str>,
+    ) -> Option<Arc<CacheItem>> {
+        let key = self.get_key(&origin);
+        if let Some(item_ref) = self.store.get(&key) {
+            if let Some(stream) = stream {
+                item_ref.set_stream(stream);
+            }
+            return Some(item_ref);
         }
 
-        if self.get_total_size().await > self.size_limit {
+        let group = self.get_group(&origin, stream);
+        if !self.evict_until_fit(None, group.as_ref()).await {
             return None;
         }
 
-        // new cache item
-        let result = Arc::new(CacheItem::new(origin.clone()));
-        self.cached.write().await.insert(origin, result.clone());
+        // new cache item, unless another request added one meanwhile
+        let (result, inserted) = self.store.get_or_insert_with(key.clone(), &|| {
+            Arc::new(CacheItem::new(
+                origin.clone(),
+                key.clone(),
+                self.get_clock(),
+                self.budget.clone(),
+                group.clone(),
+            ))
+        });
+        if let Some(stream) = stream {
+            result.set_stream(stream);
+        }
+        if !inserted {
+            return Some(result);
+        }
 
         // worker startup
         let worker_item_ref = result.clone();
@@ -69,9 +287,18 @@ impl CachePool {
     pub async fn get(
         self: &Arc<Self>,
         origin: impl AsRef<str>,
-    ) -> Result<CacheResource, io::Error> {
-        let cache_item = self.get_internal(origin.as_ref().to_owned()).await;
+    ) -> Result<Arc<SharedBuffer>, io::Error> {
+        // not in memory, the item loads it from the disk if it is there
+        let key = self.get_key(origin.as_ref());
+        let in_memory = self.store.get(&key).is_some();
+        let on_disk = match &self.disk_cache {
+            Some(disk_cache) if !in_memory => disk_cache.contains(&key).await,
+            _ => false,
+        };
+
+        let cache_item = self.get_internal(origin.as_ref().to_owned(), None).await;
         if cache_item.is_none() {
+            self.metrics.cache_rejects.inc();
             return Err(io::Error::new(
                 io::ErrorKind::OutOfMemory,
                 "Space limit reached",
@@ -79,118 +306,528 @@ impl CachePool {
         }
         let cache_item = cache_item.unwrap();
 
-        cache_item
-            .set_expire(SystemTime::now() + Duration::from_secs(self.time_limit_secs.into()))
-            .await;
+        if in_memory {
+            self.metrics.cache_hits.with_label_values(&["memory"]).inc();
+        } else if on_disk {
+            self.metrics.cache_hits.with_label_values(&["disk"]).inc();
+        } else {
+            self.metrics.cache_misses.inc();
+        }
+
+        cache_item.touch(self.get_clock());
+
+        // a failure or a stale copy is kept for its negative TTL only,
+        // and a segment with a TTL from the origin for that TTL
+        if !cache_item.buffer.is_failed()
+            && !cache_item.is_stale()
+            && cache_item.get_ttl().is_none()
+        {
+            cache_item
+                .set_expire(SystemTime::now() + Duration::from_secs(self.time_limit_secs.into()))
+                .await;
+        }
+
+        Ok(cache_item.buffer.clone())
+    }
+
+    async fn drop(self: &Arc<Self>, cache_item: &CacheItem) {
+        // it may have been evicted, and the origin is cached again by another item
+        if self
+            .store
+            .remove_if(&cache_item.key, &|x| std::ptr::eq(x.as_ref(), cache_item))
+            .is_some()
+        {
+            debug!("Resource {} dropped", cache_item.origin);
+        }
+    }
+
+    fn get_clock(&self) -> f64 {
+        *self.clock.lock().unwrap()
+    }
+
+    /// Evict the coldest items until the reserved bytes are under the limit of the pool
+    /// and the quota of `group`, returns `false` if there is nothing more to evict.
+    ///
+    /// Items still loading and items being read by requests are never evicted.
+    async fn evict_until_fit(&self, keep: Option<&CacheItem>, group: Option<&CacheGroup>) -> bool {
+        let group_exceeded = || group.is_some_and(|x| x.budget.is_exceeded());
+        if !self.budget.is_exceeded() && !group_exceeded() {
+            return true;
+        }
 
-        yield_now().await;
+        // stale copies go before any item
+        if self.budget.is_exceeded() {
+            let dropped = self.stale.shrink(|| !self.budget.is_exceeded());
+            if dropped > 0 {
+                debug!("{} stale copies dropped", dropped);
+            }
+            if !self.budget.is_exceeded() && !group_exceeded() {
+                return true;
+            }
+        }
+
+        // referenced by the store and the lifetime worker only, and no one is reading
+        let is_idle = |x: &Arc<CacheItem>| {
+            keep.is_none_or(|keep| !std::ptr::eq(x.as_ref(), keep))
+                && Arc::strong_count(x) <= 2
+                && Arc::strong_count(&x.buffer) <= 1
+                && x.get_loaded_size().is_some()
+        };
+
+        let mut candidates = self
+            .store
+            .items()
+            .into_iter()
+            .filter(|x| !self.is_pinned(x))
+            .filter_map(|x| {
+                let size = x.get_loaded_size()?;
+                let over_fair_share = self.is_over_fair_share(&x);
+                Some((
+                    x.key.clone(),
+                    size,
+                    x.get_stats(),
+                    over_fair_share,
+                    x.is_in_group(group),
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        // the groups taking more than their share go first
+        let now = Instant::now();
+        candidates.sort_by(|a, b| {
+            b.3.cmp(&a.3)
+                .then_with(|| self.eviction_policy.compare((&a.2, a.1), (&b.2, b.1), now))
+        });
 
-        let data = cache_item.get_resource().await.ok_or(io::Error::new(
-            io::ErrorKind::Interrupted,
-            "Failed to load data",
-        ))?;
+        let mut evicted = Vec::new();
+        for (key, size, stats, _, in_group) in candidates {
+            if !self.budget.is_exceeded() {
+                if !group_exceeded() {
+                    break;
+                }
 
-        Ok(data)
+                // only the quota is exceeded, it is made room by the group itself
+                if !in_group {
+                    continue;
+                }
+            }
+
+            // checked again, it may have been requested since listed
+            let Some(item) = self.store.remove_if(&key, &is_idle) else {
+                continue;
+            };
+
+            if self.eviction_policy == EvictionPolicy::Gdsf {
+                *self.clock.lock().unwrap() = self.eviction_policy.priority(&stats, size, now);
+            }
+            // spilled to the disk tier, then the lifetime worker is still waiting for expire,
+            // release the reservation now
+            if let Some(resource) = item.buffer.wait_complete().await {
+                self.spill(&item, resource).await;
+            }
+            item.buffer.fail(UNAVAILABLE);
+            evicted.push(item);
+        }
+
+        self.metrics.evictions.inc_by(evicted.len() as u64);
+        for item in evicted.iter() {
+            debug!("Resource {} evicted", item.origin);
+        }
+
+        if self.budget.is_exceeded() {
+            debug!("Space limit reached, nothing more can be evicted");
+            return false;
+        }
+        if group_exceeded() {
+            debug!(
+                "Quota of {} reached, nothing more can be evicted",
+                group.unwrap().name
+            );
+            return false;
+        }
+        true
     }
 
-    async fn drop(self: &Arc<Self>, origin: impl AsRef<str>) {
-        self.cached.write().await.remove(origin.as_ref());
-        debug!("Resource {} dropped", origin.as_ref());
+    /// Whether the group of the item reserves more than the size limit divided by active groups
+    fn is_over_fair_share(&self, cache_item: &CacheItem) -> bool {
+        if !self.quota_policy.as_ref().is_some_and(|x| x.fair_share) {
+            return false;
+        }
+
+        let Some(group) = &cache_item.group else {
+            return false;
+        };
+        let active_groups = self.groups.lock().unwrap().len().max(1);
+        group.budget.get_used() > self.budget.get_limit() / active_groups
     }
 
     async fn item_lifetime(self: &Arc<Self>, cache_item: Arc<CacheItem>) {
         let cache_item = cache_item.as_ref();
 
-        // load resource
+        // load resource, and make room for it
         self.load_item_resource(cache_item).await;
+        self.evict_until_fit(Some(cache_item), cache_item.group.as_ref())
+            .await;
 
         // wait for expire
         cache_item.wait_expire().await;
 
+        // spilled to the disk tier, and kept a while after, in case reloading it fails
+        if cache_item.buffer.is_finished()
+            && !cache_item.is_stale()
+            && cache_item.get_ttl() != Some(Duration::ZERO)
+            && let Some(resource) = cache_item.buffer.wait_complete().await
+        {
+            self.spill(cache_item, resource.clone()).await;
+            self.stale.put(cache_item.key.clone(), resource);
+        }
+
         // drop the cache, finish
-        self.drop(&cache_item.origin).await;
+        self.drop(cache_item).await;
+    }
+
+    /// Write a segment leaving memory to the disk tier, unless it is a stale copy
+    /// or the origin said not to keep it, a TTL from the origin still applies there
+    async fn spill(&self, cache_item: &CacheItem, resource: CacheResource) {
+        let Some(disk_cache) = self.disk_cache.clone() else {
+            return;
+        };
+        if cache_item.is_stale()
+            || cache_item.buffer.get_freshness().no_store
+            || disk_cache.contains(&cache_item.key).await
+        {
+            return;
+        }
+
+        let expires = match cache_item.get_ttl() {
+            Some(_) => Some(*cache_item.expire.read().await),
+            None => None,
+        };
+        if expires.is_some_and(|x| x <= SystemTime::now()) {
+            return;
+        }
+
+        let key = cache_item.key.clone();
+        tokio::spawn(async move {
+            disk_cache.put(key, &resource, expires).await;
+        });
     }
 
     async fn load_item_resource(self: &Arc<Self>, cache_item: &CacheItem) {
-        let mut write_lock = cache_item.data.write().await;
+        let buffer = &cache_item.buffer;
         tokio::select! {
-            _ = cache_item.wait_expire() => {},
-            _ = async {
-                match self.try_load_item_resource(cache_item).await {
-                    Err(e) => {
+            _ = cache_item.wait_expire() => buffer.fail(UNAVAILABLE),
+            result = self.try_load_item_resource(cache_item) => match result {
+                Err(e) => {
+                    let stale = e
+                        .class()
+                        .is_retryable()
+                        .then(|| self.stale.get(&cache_item.key))
+                        .flatten()
+                        .filter(|_| cache_item.reset_if_unread());
+                    if let Some(resource) = stale {
+                        warn!(
+                            "Error while load resource {}: {}, serving the stale copy",
+                            cache_item.origin, e
+                        );
+                        self.metrics.stale_served.with_label_values(&["segment"]).inc();
+                        cache_item.set_stale();
+                        buffer.set_head(resource.content_type, Some(resource.bytes.len() as u64));
+                        buffer.push(resource.bytes);
+                        buffer.finish();
+                    } else {
                         error!("Error while load resource {}: {}", cache_item.origin, e);
+                        buffer.fail(e.status());
                     }
-                    Ok(s) => {
-                        *write_lock = Some(s);
-                        return; // return and release the writer lock
+
+                    // reload soon
+                    cache_item
+                        .set_expire(
+                            SystemTime::now() + self.failure_policy.get_negative_ttl(e.class()),
+                        )
+                        .await;
+                }
+                Ok(()) => {
+                    buffer.finish();
+                    self.stale.remove(&cache_item.key);
+                    let freshness = buffer.get_freshness();
+                    if let Some(ttl) = self.ttl_policy.get_ttl(&freshness) {
+                        cache_item.set_ttl(ttl);
+                        cache_item.set_expire(SystemTime::now() + ttl).await;
+                    }
+
+                    if let Some(content_store) = &self.content_store
+                        && let Some(resource) = buffer.wait_complete().await
+                    {
+                        buffer.share(content_store.intern(resource.bytes), &self.budget);
                     }
                 }
-            } => {}
+            }
         };
     }
 
     async fn try_load_item_resource(
         self: &Arc<Self>,
         cache_item: &CacheItem,
-    ) -> Result<CacheResource, DownloadError> {
+    ) -> Result<(), DownloadError> {
         let origin = &cache_item.origin;
+        let buffer = &cache_item.buffer;
+
+        // promoted from the disk tier, into the budget and shared by the readers like a download
+        if let Some(disk_cache) = &self.disk_cache
+            && let Some((resource, expires)) = disk_cache.get(&cache_item.key).await
+        {
+            buffer.set_head(resource.content_type, Some(resource.bytes.len() as u64));
+            buffer.push(resource.bytes);
+            if let Some(expires) = expires {
+                let ttl = expires
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default();
+                cache_item.set_ttl(ttl);
+                cache_item.set_expire(expires).await;
+            }
+            return Ok(());
+        }
+
         debug!("Start downloading for {}", origin);
 
-        // first download, with default thread count
-        let download_result = self.downloader.download(origin, None).await;
-        let (bytes, content_type) = match download_result {
-            Ok(v) => v,
-            Err(e) => {
-                if !e.is_range_not_supported() && !e.is_content_length_missing() {
-                    return Err(e);
+        let start = Instant::now();
+        let attempts = async {
+            let mut retry = 0;
+            loop {
+                let result = match self.download(origin, buffer).await {
+                    Ok(()) => self.validate(buffer),
+                    Err(e) => Err(e),
+                };
+
+                match result {
+                    Err(e)
+                        if retry < self.failure_policy.retries
+                            && e.class().is_retryable()
+                            && !e.is_circuit_open()
+                            && cache_item.reset_if_unread() =>
+                    {
+                        let backoff = self.failure_policy.get_jittered_backoff(retry);
+                        warn!(
+                            "Error while load resource {}: {}, retry in {:?}",
+                            origin, e, backoff
+                        );
+                        sleep(backoff).await;
+                        retry += 1;
+                    }
+                    result => break result,
                 }
+            }
+        };
+        // the retries of each request and of the download multiply, bound them as a whole
+        let result = match self.failure_policy.get_load_timeout() {
+            Some(limit) => timeout(limit, attempts)
+                .await
+                .unwrap_or(Err(DownloadError::Timeout)),
+            None => attempts.await,
+        };
 
-                warn!(
-                    "Range not supported (err: {}), fallback to single-threaded for {}",
-                    e, origin
-                );
+        self.metrics
+            .download_duration
+            .observe(start.elapsed().as_secs_f64());
+        let host = Url::parse(origin)
+            .ok()
+            .and_then(|x| x.host_str().map(str::to_owned))
+            .unwrap_or_default();
+        self.metrics
+            .fetched_bytes
+            .with_label_values(&[host])
+            .inc_by(buffer.len() as u64);
+        if let Err(e) = &result {
+            self.metrics
+                .download_errors
+                .with_label_values(&[e.kind()])
+                .inc();
+        }
 
-                // range not supported by server, try download with single thread,
-                // and return error if error occurred in this time
-                self.downloader.download(origin, Some(1)).await?
+        result
+    }
+
+    /// Run the validators on a downloaded segment, before it is finished
+    fn validate(&self, buffer: &SharedBuffer) -> Result<(), DownloadError> {
+        let head = buffer.get_head();
+        let chunks = buffer.get_chunks();
+        let segment = SegmentView {
+            content_type: head.as_ref().map_or("", |x| x.content_type.as_str()),
+            content_length: head.as_ref().and_then(|x| x.content_length),
+            chunks: &chunks,
+        };
+
+        self.validators
+            .iter()
+            .try_for_each(|x| x.validate(&segment))
+            .map_err(DownloadError::InvalidContent)
+    }
+
+    async fn download(&self, origin: &str, buffer: &SharedBuffer) -> Result<(), DownloadError> {
+        let Some(sniffing) = self.sniffing.as_ref().filter(|x| x.applies_to(origin)) else {
+            return self.download_with_fallback(origin, buffer).await;
+        };
+
+        // download aside, and forward the payload once it is found,
+        // the staging buffer holds the segment until the download ends, so it counts in the budget
+        let staging = Arc::new(SharedBuffer::with_budgets(vec![self.budget.clone()]));
+        let download = async {
+            let result = self.download_with_fallback(origin, &staging).await;
+            match &result {
+                Ok(()) => staging.finish(),
+                Err(e) => staging.fail(e.status()),
             }
+            result
         };
-        debug!(
-            "Finish downloading for {} with mime {}",
-            origin, content_type
-        );
 
-        Ok(CacheResource {
-            bytes,
-            content_type,
-        })
+        let (result, _) = join!(download, sniffing.forward(staging.clone(), buffer));
+        result
     }
+
+    async fn download_with_fallback(
+        &self,
+        origin: &str,
+        buffer: &SharedBuffer,
+    ) -> Result<(), DownloadError> {
+        // first download, with default thread count
+        if let Err(e) = self.downloader.download(origin, None, buffer).await {
+            // nothing is downloaded in these cases, unless a range after the first is mismatched
+            let can_fall_back =
+                (e.is_range_not_supported() || e.is_content_length_missing()) && buffer.is_empty();
+            if !can_fall_back {
+                return Err(e);
+            }
+
+            warn!(
+                "Range not supported (err: {}), fallback to single-threaded for {}",
+                e, origin
+            );
+
+            // range not supported by server, try download with single thread,
+            // and return error if error occurred in this time
+            self.downloader.download(origin, Some(1), buffer).await?
+        }
+        debug!("Finish downloading for {}", origin);
+
+        Ok(())
+    }
+}
+
+/// Sent to clients of the items dropped while loading
+const UNAVAILABLE: u16 = 503;
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheItemState {
+    Loading,
+    Ready,
+    Failed,
+}
+
+/// A snapshot of an item in the pool
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheItemInfo {
+    pub origin: String,
+    pub key: String,
+    /// The quota group, `None` if quotas are disabled
+    pub group: Option<String>,
+    pub size: usize,
+    pub state: CacheItemState,
+    /// Unix timestamp in seconds
+    pub expire: u64,
+    /// The TTL from the origin headers (in seconds),
+    /// `None` if the item expires after it is not accessed for a while
+    pub ttl: Option<u64>,
 }
 
 #[derive(Clone, Debug)]
 pub struct CacheResource {
-    pub bytes: Arc<[u8]>,
+    pub bytes: Bytes,
     pub content_type: String,
 }
 
-struct CacheItem {
-    data: RwLock<Option<CacheResource>>,
+/// Items of the same stream (or host), sharing a quota
+#[derive(Clone)]
+struct CacheGroup {
+    name: String,
+    budget: Arc<MemoryBudget>,
+}
+
+/// A segment in the pool, loaded and dropped by its lifetime worker
+pub struct CacheItem {
+    buffer: Arc<SharedBuffer>,
+    /// The URL to download from
     origin: String,
+    key: String,
+    group: Option<CacheGroup>,
+    /// The playlist it is prepared for, if known
+    stream: Mutex<Option<String>>,
     expire: RwLock<SystemTime>,
+    /// Set once downloaded, if the origin told how long to keep it
+    ttl: Mutex<Option<Duration>>,
+    /// Holds a stale copy, as reloading it failed
+    stale: AtomicBool,
+    stats: Mutex<AccessStats>,
 }
 
 impl CacheItem {
-    pub fn new(origin: String) -> Self {
+    fn new(
+        origin: String,
+        key: String,
+        clock: f64,
+        budget: Arc<MemoryBudget>,
+        group: Option<CacheGroup>,
+    ) -> Self {
+        let mut budgets = vec![budget];
+        if let Some(group) = &group {
+            budgets.push(group.budget.clone());
+        }
+
         Self {
-            data: RwLock::new(None),
+            buffer: Arc::new(SharedBuffer::with_budgets(budgets)),
             origin,
+            key,
+            group,
+            stream: Mutex::new(None),
             expire: RwLock::new(SystemTime::now() + Duration::from_secs(30)),
+            ttl: Mutex::new(None),
+            stale: AtomicBool::new(false),
+            stats: Mutex::new(AccessStats::new(clock)),
         }
     }
 
+    fn is_in_group(&self, group: Option<&CacheGroup>) -> bool {
+        match (&self.group, group) {
+            (Some(a), Some(b)) => Arc::ptr_eq(&a.budget, &b.budget),
+            _ => false,
+        }
+    }
+
+    pub fn get_stream(&self) -> Option<String> {
+        self.stream.lock().unwrap().clone()
+    }
+
+    /// Remember the first playlist it is prepared for
+    fn set_stream(&self, stream: &str) {
+        self.stream
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| stream.to_owned());
+    }
+
+    pub fn touch(&self, clock: f64) {
+        self.stats.lock().unwrap().touch(clock);
+    }
+
+    pub fn get_stats(&self) -> AccessStats {
+        self.stats.lock().unwrap().clone()
+    }
+
     pub async fn wait_expire(&self) {
         loop {
-            let expire = self.expire.read().await.clone();
+            let expire = *self.expire.read().await;
             let now = SystemTime::now();
             if expire < now {
                 // expired
@@ -202,21 +839,318 @@ impl CacheItem {
         }
     }
 
+    pub fn get_ttl(&self) -> Option<Duration> {
+        *self.ttl.lock().unwrap()
+    }
+
+    fn set_ttl(&self, ttl: Duration) {
+        *self.ttl.lock().unwrap() = Some(ttl);
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale.load(Ordering::Relaxed)
+    }
+
+    fn set_stale(&self) {
+        self.stale.store(true, Ordering::Relaxed);
+    }
+
     pub async fn set_expire(&self, expire: SystemTime) {
         let mut expire_ref = self.expire.write().await;
         *expire_ref = expire;
     }
 
-    pub async fn get_resource(&self) -> Option<CacheResource> {
-        // wait until loaded (writer lock will release then, so we can just await the reader lock)
-        let data = self.data.read().await;
-        data.as_ref().map(|x| x.clone())
+    /// Make the buffer ready for downloading again, returns `false` if
+    /// any bytes may have been sent to clients
+    fn reset_if_unread(&self) -> bool {
+        if self.buffer.is_empty() {
+            return true;
+        }
+
+        // held by the item only
+        if Arc::strong_count(&self.buffer) > 1 {
+            return false;
+        }
+        self.buffer.reset();
+        true
     }
 
+    /// Bytes downloaded so far
     pub fn get_size(&self) -> usize {
-        match self.data.try_read() {
-            Err(_) => 0,
-            Ok(lock) => lock.as_ref().map(|x| x.bytes.len()).unwrap_or(0),
+        self.buffer.len()
+    }
+
+    /// The size of the resource, `None` if it is still loading or failed to load
+    pub fn get_loaded_size(&self) -> Option<usize> {
+        self.buffer.is_finished().then(|| self.buffer.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        env, fs,
+        net::TcpListener,
+        process,
+        sync::{Arc, Barrier, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use bytes::Bytes;
+    use reqwest::Client;
+    use tokio::{runtime::Runtime, time::sleep};
+
+    use crate::{
+        Metrics,
+        caching::{
+            CacheItem, CachePolicies, CachePool, CacheResource, CacheStore, DiskCache, Downloader,
+            FailurePolicy, MemoryStore, NegativeTtl, RequestRetries, ShardedStore,
+        },
+    };
+
+    /// Keeps items in a plain map, and records the keys removed
+    #[derive(Default)]
+    struct FakeStore {
+        items: Mutex<HashMap<String, Arc<CacheItem>>>,
+        removed: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl CacheStore for FakeStore {
+        fn get(&self, key: &str) -> Option<Arc<CacheItem>> {
+            self.items.lock().unwrap().get(key).cloned()
+        }
+
+        fn put(&self, key: String, item: Arc<CacheItem>) -> Option<Arc<CacheItem>> {
+            self.items.lock().unwrap().insert(key, item)
         }
+
+        fn get_or_insert_with(
+            &self,
+            key: String,
+            create: &dyn Fn() -> Arc<CacheItem>,
+        ) -> (Arc<CacheItem>, bool) {
+            let mut items = self.items.lock().unwrap();
+            if let Some(item) = items.get(&key) {
+                return (item.clone(), false);
+            }
+            let item = create();
+            items.insert(key, item.clone());
+            (item, true)
+        }
+
+        fn remove_if(
+            &self,
+            key: &str,
+            predicate: &dyn Fn(&Arc<CacheItem>) -> bool,
+        ) -> Option<Arc<CacheItem>> {
+            let mut items = self.items.lock().unwrap();
+            if !items.get(key).is_some_and(predicate) {
+                return None;
+            }
+            self.removed.lock().unwrap().push(key.to_owned());
+            items.remove(key)
+        }
+
+        fn items(&self) -> Vec<Arc<CacheItem>> {
+            self.items.lock().unwrap().values().cloned().collect()
+        }
+
+        fn len(&self) -> usize {
+            self.items.lock().unwrap().len()
+        }
+    }
+
+    /// A pool whose downloads fail at once, and are forgotten at once
+    fn create_pool(size_limit: usize) -> (Arc<CachePool>, Arc<Mutex<Vec<String>>>) {
+        create_pool_with(size_limit, None)
+    }
+
+    fn create_pool_with(
+        size_limit: usize,
+        disk_cache: Option<Arc<DiskCache>>,
+    ) -> (Arc<CachePool>, Arc<Mutex<Vec<String>>>) {
+        let store = FakeStore::default();
+        let removed = store.removed.clone();
+        let failure = FailurePolicy {
+            retries: 0,
+            request_retries: RequestRetries {
+                server_error: 0,
+                timeout: 0,
+                other: 0,
+            },
+            negative_ttl: NegativeTtl {
+                other: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let policies = CachePolicies {
+            failure: failure.clone(),
+            ..Default::default()
+        };
+
+        let pool = CachePool::new(
+            size_limit,
+            30,
+            policies,
+            Box::new(store),
+            Arc::new(Downloader::new(Client::new(), 1, failure)),
+            disk_cache,
+            Metrics::new(),
+        );
+        (pool, removed)
+    }
+
+    // nothing listens on the discard port
+    const UNREACHABLE: &str = "http://127.0.0.1:9/0.ts";
+
+    #[test]
+    fn test_admission() {
+        let (pool, removed) = create_pool(8);
+        Runtime::new().unwrap().block_on(async {
+            let key = "http://example.com/0.ts".to_owned();
+            let item = Arc::new(CacheItem::new(
+                key.clone(),
+                key.clone(),
+                0.0,
+                pool.budget.clone(),
+                None,
+            ));
+            item.buffer.push(Bytes::from_static(b"0123456789"));
+            pool.store.put(key.clone(), item.clone());
+
+            // an item still loading is never evicted
+            assert!(pool.get(UNREACHABLE).await.is_err());
+            assert!(removed.lock().unwrap().is_empty());
+
+            item.buffer.finish();
+            assert!(pool.get(UNREACHABLE).await.is_ok());
+            // the new item may be dropped already too, as its download fails at once
+            assert_eq!(removed.lock().unwrap().first(), Some(&key));
+            assert!(item.buffer.is_failed());
+        });
+    }
+
+    #[test]
+    fn test_pinned() {
+        let (pool, removed) = create_pool(8);
+        Runtime::new().unwrap().block_on(async {
+            let items = ["http://a/0.ts", "http://b/0.ts"].map(|key| {
+                let item = Arc::new(CacheItem::new(
+                    key.into(),
+                    key.into(),
+                    0.0,
+                    pool.budget.clone(),
+                    None,
+                ));
+                item.buffer.push(Bytes::from_static(b"01234"));
+                item.buffer.finish();
+                pool.store.put(key.into(), item.clone());
+                item
+            });
+            items[0].set_stream("http://a/live.m3u8");
+            items[1].set_stream("http://b/live.m3u8");
+            pool.pin_for("http://a/live.m3u8", Duration::from_secs(60));
+
+            // the older one goes first, unless it is pinned
+            pool.get(UNREACHABLE).await.unwrap();
+            assert_eq!(removed.lock().unwrap().first().unwrap(), "http://b/0.ts");
+            assert!(!items[0].buffer.is_failed());
+            assert!(items[1].buffer.is_failed());
+        });
+    }
+
+    #[test]
+    fn test_failure_expire() {
+        let (pool, _) = create_pool(1024);
+        Runtime::new().unwrap().block_on(async {
+            let buffer = pool.get(UNREACHABLE).await.unwrap();
+            assert_eq!(buffer.wait_head().await.unwrap_err(), 502);
+
+            // dropped once its negative TTL passed
+            for _ in 0..50 {
+                if pool.store.is_empty() {
+                    break;
+                }
+                sleep(Duration::from_millis(20)).await;
+            }
+            assert!(pool.store.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_concurrent_get() {
+        let (pool, _) = create_pool(1024);
+        let stores: [Box<dyn CacheStore>; 2] = [
+            Box::new(MemoryStore::default()),
+            Box::new(ShardedStore::new(4)),
+        ];
+        for store in stores {
+            let store = Arc::new(store);
+            let barrier = Arc::new(Barrier::new(8));
+            let threads = (0..8)
+                .map(|_| {
+                    let (pool, store, barrier) = (pool.clone(), store.clone(), barrier.clone());
+                    thread::spawn(move || {
+                        barrier.wait();
+                        store.get_or_insert_with("a".into(), &|| {
+                            Arc::new(CacheItem::new(
+                                "a".into(),
+                                "a".into(),
+                                0.0,
+                                pool.budget.clone(),
+                                None,
+                            ))
+                        })
+                    })
+                })
+                .collect::<Vec<_>>();
+            let results = threads
+                .into_iter()
+                .map(|x| x.join().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(results.iter().filter(|(_, inserted)| *inserted).count(), 1);
+            assert!(results.iter().all(|(x, _)| Arc::ptr_eq(x, &results[0].0)));
+        }
+
+        // accepts connections but never responds, so the download stays pending
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let origin = format!("http://{}/0.ts", listener.local_addr().unwrap());
+        Runtime::new().unwrap().block_on(async {
+            let gets = [pool.clone(), pool.clone()].map(|pool| {
+                let origin = origin.clone();
+                tokio::spawn(async move { pool.get(origin).await.unwrap() })
+            });
+            let [a, b] = gets;
+            let (a, b) = (a.await.unwrap(), b.await.unwrap());
+            assert!(Arc::ptr_eq(&a, &b));
+            assert_eq!(pool.store.len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_disk_hit() {
+        let directory = env::temp_dir().join(format!("swiftstream-promote-{}", process::id()));
+        _ = fs::remove_dir_all(&directory);
+        let disk_cache = DiskCache::open(&directory, 1024).unwrap();
+        let (pool, _) = create_pool_with(1024, Some(disk_cache.clone()));
+
+        Runtime::new().unwrap().block_on(async {
+            let resource = CacheResource {
+                bytes: Bytes::from_static(b"segment"),
+                content_type: "video/mp2t".into(),
+            };
+            disk_cache.put(UNREACHABLE, &resource, None).await;
+
+            // promoted into memory, the readers share one buffer charged to the budget
+            let a = pool.get(UNREACHABLE).await.unwrap();
+            let b = pool.get(UNREACHABLE).await.unwrap();
+            assert!(Arc::ptr_eq(&a, &b));
+            assert_eq!(a.wait_complete().await.unwrap().bytes, resource.bytes);
+            assert_eq!(pool.budget.get_used(), resource.bytes.len());
+        });
+        _ = fs::remove_dir_all(&directory);
     }
 }
@@ -0,0 +1,168 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Arc, RwLock},
+};
+
+use serde::Deserialize;
+
+use crate::caching::CacheItem;
+
+/// Where `CachePool` keeps its items, by their cache keys
+pub trait CacheStore: Send + Sync {
+    fn get(&self, key: &str) -> Option<Arc<CacheItem>>;
+
+    /// Returns the item replaced
+    fn put(&self, key: String, item: Arc<CacheItem>) -> Option<Arc<CacheItem>>;
+
+    /// The item of `key`, or the one `create` makes if there is none, it is checked
+    /// atomically with the insertion, returns whether the item is the new one
+    fn get_or_insert_with(
+        &self,
+        key: String,
+        create: &dyn Fn() -> Arc<CacheItem>,
+    ) -> (Arc<CacheItem>, bool);
+
+    /// Remove the item only if `predicate` holds, it is checked atomically with the removal
+    fn remove_if(
+        &self,
+        key: &str,
+        predicate: &dyn Fn(&Arc<CacheItem>) -> bool,
+    ) -> Option<Arc<CacheItem>>;
+
+    fn remove(&self, key: &str) -> Option<Arc<CacheItem>> {
+        self.remove_if(key, &|_| true)
+    }
+
+    /// A snapshot of all items
+    fn items(&self) -> Vec<Arc<CacheItem>>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Which `CacheStore` the pool uses
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheStoreKind {
+    /// One map behind a lock
+    #[default]
+    Memory,
+    /// Maps split by key hash, less contention with many concurrent requests
+    Sharded,
+}
+
+impl CacheStoreKind {
+    pub fn create(&self) -> Box<dyn CacheStore> {
+        match self {
+            Self::Memory => Box::new(MemoryStore::default()),
+            Self::Sharded => Box::new(ShardedStore::new(SHARDS)),
+        }
+    }
+}
+
+const SHARDS: usize = 16;
+
+#[derive(Default)]
+pub struct MemoryStore {
+    items: RwLock<HashMap<String, Arc<CacheItem>>>,
+}
+
+impl CacheStore for MemoryStore {
+    fn get(&self, key: &str) -> Option<Arc<CacheItem>> {
+        self.items.read().unwrap().get(key).cloned()
+    }
+
+    fn put(&self, key: String, item: Arc<CacheItem>) -> Option<Arc<CacheItem>> {
+        self.items.write().unwrap().insert(key, item)
+    }
+
+    fn get_or_insert_with(
+        &self,
+        key: String,
+        create: &dyn Fn() -> Arc<CacheItem>,
+    ) -> (Arc<CacheItem>, bool) {
+        let mut items = self.items.write().unwrap();
+        if let Some(item) = items.get(&key) {
+            return (item.clone(), false);
+        }
+        let item = create();
+        items.insert(key, item.clone());
+        (item, true)
+    }
+
+    fn remove_if(
+        &self,
+        key: &str,
+        predicate: &dyn Fn(&Arc<CacheItem>) -> bool,
+    ) -> Option<Arc<CacheItem>> {
+        let mut items = self.items.write().unwrap();
+        if !items.get(key).is_some_and(predicate) {
+            return None;
+        }
+        items.remove(key)
+    }
+
+    fn items(&self) -> Vec<Arc<CacheItem>> {
+        self.items.read().unwrap().values().cloned().collect()
+    }
+
+    fn len(&self) -> usize {
+        self.items.read().unwrap().len()
+    }
+}
+
+pub struct ShardedStore {
+    shards: Vec<MemoryStore>,
+}
+
+impl ShardedStore {
+    pub fn new(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1)).map(|_| MemoryStore::default()).collect(),
+        }
+    }
+
+    fn get_shard(&self, key: &str) -> &MemoryStore {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+}
+
+impl CacheStore for ShardedStore {
+    fn get(&self, key: &str) -> Option<Arc<CacheItem>> {
+        self.get_shard(key).get(key)
+    }
+
+    fn put(&self, key: String, item: Arc<CacheItem>) -> Option<Arc<CacheItem>> {
+        self.get_shard(&key).put(key, item)
+    }
+
+    fn get_or_insert_with(
+        &self,
+        key: String,
+        create: &dyn Fn() -> Arc<CacheItem>,
+    ) -> (Arc<CacheItem>, bool) {
+        self.get_shard(&key).get_or_insert_with(key, create)
+    }
+
+    fn remove_if(
+        &self,
+        key: &str,
+        predicate: &dyn Fn(&Arc<CacheItem>) -> bool,
+    ) -> Option<Arc<CacheItem>> {
+        self.get_shard(key).remove_if(key, predicate)
+    }
+
+    fn items(&self) -> Vec<Arc<CacheItem>> {
+        self.shards.iter().flat_map(|x| x.items()).collect()
+    }
+
+    fn len(&self) -> usize {
+        self.shards.iter().map(|x| x.len()).sum()
+    }
+}
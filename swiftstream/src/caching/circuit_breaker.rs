@@ -0,0 +1,162 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::{error, info};
+use serde::Deserialize;
+
+/// When requests to a failing origin stop being sent
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CircuitBreakerConfig {
+    /// Failed requests in a row that open the circuit of a host, 0 to disable
+    pub failures: u32,
+    /// How long requests to the host fail fast once the circuit is open (in seconds),
+    /// then a single request is sent, the others fail fast until it succeeds,
+    /// and the circuit opens again if it fails
+    pub cooldown: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failures: 5,
+            cooldown: 10,
+        }
+    }
+}
+
+/// A circuit breaker for each host
+pub struct CircuitBreakers {
+    config: CircuitBreakerConfig,
+    hosts: Mutex<HashMap<String, HostCircuit>>,
+}
+
+#[derive(Default)]
+struct HostCircuit {
+    failures: u32,
+    open_until: Option<Instant>,
+    /// When the request probing the origin after the cooldown was let through
+    probe_since: Option<Instant>,
+}
+
+impl CircuitBreakers {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether a request to the host may be sent now, once the cooldown passes
+    /// only one is let through to probe the origin, it must be followed by a record
+    pub fn try_acquire(&self, host: &str) -> bool {
+        let mut hosts = self.hosts.lock().unwrap();
+        let Some(circuit) = hosts.get_mut(host) else {
+            return true;
+        };
+        let Some(open_until) = circuit.open_until else {
+            return true;
+        };
+
+        // a probe never recorded (its download dropped) is replaced after a cooldown
+        let now = Instant::now();
+        let cooldown = Duration::from_secs(self.config.cooldown);
+        if open_until > now || circuit.probe_since.is_some_and(|x| x + cooldown > now) {
+            return false;
+        }
+        circuit.probe_since = Some(now);
+        true
+    }
+
+    /// Whether requests to the host fail fast, without taking the probe
+    pub fn is_open(&self, host: &str) -> bool {
+        let hosts = self.hosts.lock().unwrap();
+        hosts
+            .get(host)
+            .and_then(|x| x.open_until)
+            .is_some_and(|x| x > Instant::now())
+    }
+
+    pub fn record_success(&self, host: &str) {
+        let mut hosts = self.hosts.lock().unwrap();
+        if let Some(circuit) = hosts.remove(host)
+            && circuit.open_until.is_some()
+        {
+            info!("Circuit of {} closed, the origin is back", host);
+        }
+    }
+
+    pub fn record_failure(&self, host: &str) {
+        if self.config.failures == 0 {
+            return;
+        }
+
+        let mut hosts = self.hosts.lock().unwrap();
+        let circuit = hosts.entry(host.to_owned()).or_default();
+        circuit.failures += 1;
+        circuit.probe_since = None;
+        if circuit.failures < self.config.failures {
+            return;
+        }
+
+        // open, or open again after the cooldown
+        let now = Instant::now();
+        if circuit.open_until.is_none_or(|x| x <= now) {
+            let cooldown = Duration::from_secs(self.config.cooldown);
+            error!(
+                "Circuit of {} opened after {} failed requests in a row, failing fast for {:?}",
+                host, circuit.failures, cooldown
+            );
+            circuit.open_until = Some(now + cooldown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread::sleep, time::Duration};
+
+    use crate::caching::{CircuitBreakerConfig, CircuitBreakers};
+
+    #[test]
+    fn test_circuit() {
+        let breakers = CircuitBreakers::new(CircuitBreakerConfig {
+            failures: 2,
+            cooldown: 0,
+        });
+
+        breakers.record_failure("a");
+        assert!(breakers.try_acquire("a"));
+        breakers.record_success("a");
+        breakers.record_failure("a");
+        assert!(breakers.try_acquire("a"));
+
+        let breakers = CircuitBreakers::new(CircuitBreakerConfig {
+            failures: 2,
+            cooldown: 1,
+        });
+        breakers.record_failure("a");
+        breakers.record_failure("a");
+        assert!(breakers.is_open("a"));
+        assert!(!breakers.try_acquire("a"));
+        assert!(breakers.try_acquire("b"));
+
+        // half open after the cooldown, a single probe is let through
+        sleep(Duration::from_millis(1100));
+        assert!(!breakers.is_open("a"));
+        assert!(breakers.try_acquire("a"));
+        assert!(!breakers.try_acquire("a"));
+        breakers.record_failure("a");
+        assert!(breakers.is_open("a"));
+        assert!(!breakers.try_acquire("a"));
+
+        sleep(Duration::from_millis(1100));
+        assert!(breakers.try_acquire("a"));
+        breakers.record_success("a");
+        assert!(breakers.try_acquire("a"));
+        assert!(breakers.try_acquire("a"));
+    }
+}
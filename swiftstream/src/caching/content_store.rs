@@ -0,0 +1,139 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Arc, Mutex, Weak},
+};
+
+use bytes::Bytes;
+use serde::Serialize;
+
+use crate::caching::MemoryBudget;
+
+/// Payloads indexed by their content, so segments with identical bytes
+/// (from mirrors, tokenized URLs...) share one copy in memory
+pub struct ContentStore {
+    entries: Mutex<HashMap<u64, Vec<Weak<ContentEntry>>>>,
+    budget: Arc<MemoryBudget>,
+}
+
+/// A payload, accounted in the budget once, until all the buffers holding it are dropped
+struct ContentEntry {
+    bytes: Bytes,
+    budget: Arc<MemoryBudget>,
+}
+
+impl Drop for ContentEntry {
+    fn drop(&mut self) {
+        self.budget.release(self.bytes.len());
+    }
+}
+
+/// Keeps the entry alive as long as any `Bytes` refers to it
+struct ContentRef(Arc<ContentEntry>);
+
+impl AsRef<[u8]> for ContentRef {
+    fn as_ref(&self) -> &[u8] {
+        &self.0.bytes
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentStats {
+    /// Distinct payloads held
+    pub contents: usize,
+    /// Bytes of the distinct payloads
+    pub stored_bytes: usize,
+    /// Bytes that would be held without sharing
+    pub saved_bytes: usize,
+}
+
+impl ContentStore {
+    pub fn new(budget: Arc<MemoryBudget>) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            budget,
+        }
+    }
+
+    /// The shared copy of `bytes`, it is added to the store if there is none
+    pub fn intern(&self, bytes: Bytes) -> Bytes {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let mut entries = self.entries.lock().unwrap();
+        let bucket = entries.entry(hash).or_default();
+        bucket.retain(|x| x.strong_count() > 0);
+
+        let entry = match bucket
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|x| x.bytes == bytes)
+        {
+            Some(entry) => entry,
+            None => {
+                self.budget.reserve(bytes.len());
+                let entry = Arc::new(ContentEntry {
+                    bytes,
+                    budget: self.budget.clone(),
+                });
+                bucket.push(Arc::downgrade(&entry));
+                entry
+            }
+        };
+
+        Bytes::from_owner(ContentRef(entry))
+    }
+
+    pub fn get_stats(&self) -> ContentStats {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, bucket| {
+            bucket.retain(|x| x.strong_count() > 0);
+            !bucket.is_empty()
+        });
+
+        let mut stats = ContentStats::default();
+        for entry in entries.values().flatten().filter_map(Weak::upgrade) {
+            // the references held by buffers, not counting the one upgraded here
+            let holders = Arc::strong_count(&entry) - 1;
+            stats.contents += 1;
+            stats.stored_bytes += entry.bytes.len();
+            stats.saved_bytes += holders.saturating_sub(1) * entry.bytes.len();
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+
+    use crate::caching::{ContentStore, MemoryBudget, SharedBuffer};
+
+    #[test]
+    fn test_dedupe() {
+        let budget = Arc::new(MemoryBudget::new(1024));
+        let store = ContentStore::new(budget.clone());
+
+        let buffers = ["abcdef", "abcdef", "ghijkl"].map(|x| {
+            let buffer = SharedBuffer::with_budgets(vec![budget.clone()]);
+            buffer.push(Bytes::from_static(x.as_bytes()));
+            buffer.finish();
+            buffer.share(store.intern(Bytes::from_static(x.as_bytes())), &budget);
+            buffer
+        });
+
+        // the identical payloads are accounted once
+        assert_eq!(budget.get_used(), 12);
+        let stats = store.get_stats();
+        assert_eq!(stats.contents, 2);
+        assert_eq!(stats.saved_bytes, 6);
+
+        drop(buffers);
+        assert_eq!(budget.get_used(), 0);
+        assert_eq!(store.get_stats().contents, 0);
+    }
+}
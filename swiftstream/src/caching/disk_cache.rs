@@ -0,0 +1,396 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{io, sync::RwLock};
+
+use crate::caching::CacheResource;
+
+const DATA_EXTENSION: &str = "seg";
+const META_EXTENSION: &str = "meta";
+const TEMP_EXTENSION: &str = "tmp";
+
+/// The second cache tier, segments leaving memory are kept as files in a directory,
+/// and the least recently used ones are removed when the size limit is reached
+pub struct DiskCache {
+    directory: PathBuf,
+    size_limit: u64,
+    index: RwLock<DiskIndex>,
+}
+
+#[derive(Default)]
+struct DiskIndex {
+    entries: HashMap<String, DiskEntry>,
+    total_size: u64,
+}
+
+struct DiskEntry {
+    file_stem: String,
+    size: u64,
+    content_type: String,
+    last_access: SystemTime,
+    /// When the TTL the origin gave runs out, if it gave one
+    expires: Option<SystemTime>,
+}
+
+impl DiskEntry {
+    fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|x| x <= SystemTime::now())
+    }
+}
+
+/// Stored beside the data file, so the index can be rebuilt on startup
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DiskMeta {
+    origin: String,
+    content_type: String,
+    /// In seconds since the Unix epoch
+    #[serde(default)]
+    expires: Option<u64>,
+}
+
+impl DiskCache {
+    /// Open the directory (create it if not exists), and rebuild the index from the files in it
+    pub fn open(directory: impl AsRef<Path>, size_limit: u64) -> Result<Arc<Self>, io::Error> {
+        let directory = directory.as_ref().to_owned();
+        fs::create_dir_all(&directory)?;
+
+        let mut index = DiskIndex::default();
+        for entry in fs::read_dir(&directory)? {
+            let path = entry?.path();
+            match path.extension().and_then(|x| x.to_str()) {
+                Some(META_EXTENSION) => {}
+                // written just before a crash, before its meta
+                Some(DATA_EXTENSION) => {
+                    if !path.with_extension(META_EXTENSION).exists() {
+                        _ = fs::remove_file(&path);
+                    }
+                    continue;
+                }
+                // unfinished writes
+                Some(TEMP_EXTENSION) => {
+                    _ = fs::remove_file(&path);
+                    continue;
+                }
+                _ => continue,
+            }
+
+            match Self::load_entry(&path) {
+                Ok((origin, entry)) => {
+                    index.total_size += entry.size;
+                    index.entries.insert(origin, entry);
+                }
+                Err(e) => {
+                    warn!("Drop broken disk cache entry {}: {}", path.display(), e);
+                    _ = fs::remove_file(&path);
+                    _ = fs::remove_file(path.with_extension(DATA_EXTENSION));
+                }
+            }
+        }
+
+        info!(
+            "Disk cache {} opened with {} entries ({} bytes)",
+            directory.display(),
+            index.entries.len(),
+            index.total_size
+        );
+
+        Ok(Arc::new(Self {
+            directory,
+            size_limit,
+            index: RwLock::new(index),
+        }))
+    }
+
+    fn load_entry(meta_path: &Path) -> Result<(String, DiskEntry), anyhow::Error> {
+        let meta: DiskMeta = serde_yaml::from_reader(fs::File::open(meta_path)?)?;
+        let data = fs::metadata(meta_path.with_extension(DATA_EXTENSION))?;
+        let file_stem = meta_path
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .unwrap_or_default()
+            .to_owned();
+
+        Ok((
+            meta.origin,
+            DiskEntry {
+                file_stem,
+                size: data.len(),
+                content_type: meta.content_type,
+                last_access: data.modified().unwrap_or_else(|_| SystemTime::now()),
+                expires: meta.expires.map(|x| UNIX_EPOCH + Duration::from_secs(x)),
+            },
+        ))
+    }
+
+    fn data_path(&self, file_stem: &str) -> PathBuf {
+        self.directory
+            .join(file_stem)
+            .with_extension(DATA_EXTENSION)
+    }
+
+    fn meta_path(&self, file_stem: &str) -> PathBuf {
+        self.directory
+            .join(file_stem)
+            .with_extension(META_EXTENSION)
+    }
+
+    /// Whether an entry of `origin` is kept and not expired
+    pub async fn contains(&self, origin: impl AsRef<str>) -> bool {
+        self.index
+            .read()
+            .await
+            .entries
+            .get(origin.as_ref())
+            .is_some_and(|x| !x.is_expired())
+    }
+
+    /// The cached resource, and when it expires if it has a TTL
+    pub async fn get(
+        &self,
+        origin: impl AsRef<str>,
+    ) -> Option<(CacheResource, Option<SystemTime>)> {
+        let origin = origin.as_ref();
+        let (file_stem, content_type, expires) = {
+            let mut index = self.index.write().await;
+            let entry = index.entries.get_mut(origin)?;
+            if entry.is_expired() {
+                drop(index);
+                debug!("Disk cache of {} expired", origin);
+                self.remove(origin).await;
+                return None;
+            }
+            entry.last_access = SystemTime::now();
+            (
+                entry.file_stem.clone(),
+                entry.content_type.clone(),
+                entry.expires,
+            )
+        };
+
+        match tokio::fs::read(self.data_path(&file_stem)).await {
+            Ok(bytes) => {
+                debug!("Disk cache hit for {}", origin);
+                let resource = CacheResource {
+                    bytes: Bytes::from(bytes),
+                    content_type,
+                };
+                Some((resource, expires))
+            }
+            Err(e) => {
+                warn!("Failed to read disk cache of {}: {}", origin, e);
+                self.remove(origin).await;
+                None
+            }
+        }
+    }
+
+    /// Keep `resource` until it is evicted, or `expires` if set
+    pub async fn put(
+        &self,
+        origin: impl AsRef<str>,
+        resource: &CacheResource,
+        expires: Option<SystemTime>,
+    ) {
+        let origin = origin.as_ref();
+        let size = resource.bytes.len() as u64;
+        if size > self.size_limit || self.contains(origin).await {
+            return;
+        }
+
+        // make room for it
+        self.evict_until_fit(size).await;
+
+        // the files are shared by the origins with the same hash, keep only the newest one
+        let file_stem = format!("{:016x}", fnv1a(origin.as_bytes()));
+        let collided = self
+            .index
+            .read()
+            .await
+            .entries
+            .iter()
+            .find(|x| x.1.file_stem == file_stem)
+            .map(|x| x.0.clone());
+        if let Some(collided) = collided {
+            self.remove(collided).await;
+        }
+
+        if let Err(e) = self
+            .write_files(&file_stem, origin, resource, expires)
+            .await
+        {
+            warn!("Failed to write disk cache of {}: {}", origin, e);
+            _ = tokio::fs::remove_file(self.data_path(&file_stem)).await;
+            _ = tokio::fs::remove_file(self.meta_path(&file_stem)).await;
+            return;
+        }
+
+        let mut index = self.index.write().await;
+        index.total_size += size;
+        if let Some(replaced) = index.entries.insert(
+            origin.to_owned(),
+            DiskEntry {
+                file_stem,
+                size,
+                content_type: resource.content_type.clone(),
+                last_access: SystemTime::now(),
+                expires,
+            },
+        ) {
+            index.total_size -= replaced.size;
+        }
+        debug!("Resource {} written to disk cache", origin);
+    }
+
+    async fn write_files(
+        &self,
+        file_stem: &str,
+        origin: &str,
+        resource: &CacheResource,
+        expires: Option<SystemTime>,
+    ) -> Result<(), anyhow::Error> {
+        // write into a temporary file first, so a crash never leaves a truncated segment
+        let temp_path = self
+            .directory
+            .join(file_stem)
+            .with_extension(TEMP_EXTENSION);
+        tokio::fs::write(&temp_path, &resource.bytes).await?;
+        tokio::fs::rename(&temp_path, self.data_path(file_stem)).await?;
+
+        let meta = serde_yaml::to_string(&DiskMeta {
+            origin: origin.to_owned(),
+            content_type: resource.content_type.clone(),
+            expires: expires
+                .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+                .map(|x| x.as_secs()),
+        })?;
+        tokio::fs::write(self.meta_path(file_stem), meta).await?;
+
+        Ok(())
+    }
+
+    pub async fn remove(&self, origin: impl AsRef<str>) {
+        let removed = {
+            let mut index = self.index.write().await;
+            let removed = index.entries.remove(origin.as_ref());
+            if let Some(entry) = &removed {
+                index.total_size -= entry.size;
+            }
+            removed
+        };
+
+        if let Some(entry) = removed {
+            _ = tokio::fs::remove_file(self.meta_path(&entry.file_stem)).await;
+            _ = tokio::fs::remove_file(self.data_path(&entry.file_stem)).await;
+        }
+    }
+
+    /// Remove the entries whose origin matches, returns how many are removed
+    pub async fn purge(&self, matches: impl Fn(&str) -> bool) -> usize {
+        let origins = self
+            .index
+            .read()
+            .await
+            .entries
+            .keys()
+            .filter(|x| matches(x))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for origin in origins.iter() {
+            self.remove(origin).await;
+        }
+
+        origins.len()
+    }
+
+    async fn evict_until_fit(&self, size: u64) {
+        loop {
+            let victim = {
+                let index = self.index.read().await;
+                if index.total_size + size <= self.size_limit {
+                    return;
+                }
+
+                index
+                    .entries
+                    .iter()
+                    .min_by_key(|x| x.1.last_access)
+                    .map(|x| x.0.clone())
+            };
+
+            match victim {
+                Some(origin) => {
+                    debug!("Resource {} evicted from disk cache", origin);
+                    self.remove(origin).await;
+                }
+                None => return,
+            }
+        }
+    }
+}
+
+/// FNV-1a, stable between runs, so file names can be found again after restart
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process, time::SystemTime};
+
+    use bytes::Bytes;
+    use tokio::runtime::Runtime;
+
+    use crate::caching::{CacheResource, DiskCache};
+
+    #[test]
+    fn test_reopen() {
+        let directory = env::temp_dir().join(format!("swiftstream-disk-{}", process::id()));
+        let resource = CacheResource {
+            bytes: Bytes::from_static(b"segment"),
+            content_type: "video/mp2t".into(),
+        };
+
+        Runtime::new().unwrap().block_on(async {
+            let disk_cache = DiskCache::open(&directory, 1024).unwrap();
+            disk_cache
+                .put("http://example.com/0.ts", &resource, None)
+                .await;
+            disk_cache
+                .put(
+                    "http://example.com/1.ts",
+                    &resource,
+                    Some(SystemTime::now()),
+                )
+                .await;
+        });
+        // a data file whose meta was never written
+        fs::write(directory.join("orphan.seg"), b"segment").unwrap();
+
+        Runtime::new().unwrap().block_on(async {
+            let disk_cache = DiskCache::open(&directory, 1024).unwrap();
+            let (cached, expires) = disk_cache.get("http://example.com/0.ts").await.unwrap();
+            assert_eq!(cached.bytes, resource.bytes);
+            assert!(expires.is_none());
+            // its TTL ran out before the restart
+            assert!(!disk_cache.contains("http://example.com/1.ts").await);
+            assert!(disk_cache.get("http://example.com/1.ts").await.is_none());
+            assert!(!directory.join("orphan.seg").exists());
+        });
+        _ = fs::remove_dir_all(&directory);
+    }
+}
@@ -1,18 +1,221 @@
 use std::{error::Error, fmt::Display, sync::Arc};
 
-use bytes::{BufMut, BytesMut};
-use futures::future::join_all;
-use reqwest::{Client, header};
+use bytes::{Bytes, BytesMut};
+use log::{debug, warn};
+use reqwest::{Client, RequestBuilder, Response, StatusCode, header};
+use tokio::{
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+use url::Url;
+
+use crate::caching::{CircuitBreakers, FailurePolicy, Freshness, SharedBuffer};
 
 pub struct Downloader {
-    http_client: Client,
+    requester: Requester,
     default_threads: u8,
 }
 
+/// Sends the requests of downloads, retried by the failure policy
+/// and guarded by the circuit breaker of each host
+#[derive(Clone)]
+struct Requester {
+    http_client: Client,
+    policy: Arc<FailurePolicy>,
+    breakers: Arc<CircuitBreakers>,
+}
+
+impl Requester {
+    /// Run a step of a request within the attempt timeout
+    async fn with_timeout<T>(
+        &self,
+        step: impl Future<Output = Result<T, reqwest::Error>>,
+    ) -> Result<T, DownloadError> {
+        match self.policy.get_attempt_timeout() {
+            Some(duration) => timeout(duration, step)
+                .await
+                .map_err(|_| DownloadError::Timeout)?
+                .map_err(DownloadError::from),
+            None => step.await.map_err(DownloadError::from),
+        }
+    }
+
+    /// Send the request, retried until it succeeds or its failure is not worth retrying
+    async fn send(
+        &self,
+        host: &str,
+        request: impl Fn() -> RequestBuilder,
+    ) -> Result<Response, DownloadError> {
+        let mut retry = 0;
+        loop {
+            if !self.breakers.try_acquire(host) {
+                return Err(DownloadError::CircuitOpen(host.to_owned()));
+            }
+
+            let result = match self.with_timeout(request().send()).await {
+                Ok(response) if !response.status().is_success() => {
+                    Err(DownloadError::RequestNotSuccess(response.status().as_u16()))
+                }
+                result => result,
+            };
+
+            match result {
+                Ok(response) => {
+                    self.breakers.record_success(host);
+                    return Ok(response);
+                }
+                Err(e) => {
+                    retry = self.wait_retry(host, e, retry).await?;
+                }
+            }
+        }
+    }
+
+    /// Request a range, `end` is inclusive, `None` for the end of the resource,
+    /// the response must be a 206 with exactly this range
+    async fn send_range(
+        &self,
+        host: &str,
+        origin: &str,
+        start: u64,
+        end: Option<u64>,
+    ) -> Result<Response, DownloadError> {
+        let range = match end {
+            Some(end) => format!("bytes={}-{}", start, end),
+            None => format!("bytes={}-", start),
+        };
+        let response = self
+            .send(host, || {
+                self.http_client
+                    .get(origin)
+                    .header(header::RANGE, range.as_str())
+            })
+            .await?;
+
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(DownloadError::RangeNotSupported);
+        }
+        let content_range = ContentRange::from_headers(response.headers())
+            .ok_or(DownloadError::RangeNotSupported)?;
+        let expected_end = end.or(content_range.total.map(|x| x.saturating_sub(1)));
+        if content_range.start != start || Some(content_range.end) != expected_end {
+            return Err(DownloadError::RangeMismatch(
+                range,
+                content_range.to_string(),
+            ));
+        }
+
+        Ok(response)
+    }
+
+    /// Record a failed attempt, and wait before the next one,
+    /// returns the error if it is not retried
+    async fn wait_retry(
+        &self,
+        host: &str,
+        e: DownloadError,
+        retry: u32,
+    ) -> Result<u32, DownloadError> {
+        if !e.is_origin_failure() {
+            // the origin did respond
+            self.breakers.record_success(host);
+            return Err(e);
+        }
+        self.breakers.record_failure(host);
+
+        if retry >= self.policy.get_request_retries(e.class()) || self.breakers.is_open(host) {
+            return Err(e);
+        }
+        let backoff = self.policy.get_jittered_backoff(retry);
+        warn!("Request to {} failed: {}, retry in {:?}", host, e, backoff);
+        sleep(backoff).await;
+        Ok(retry + 1)
+    }
+
+    /// Pass the body on as it arrives, the rest is requested again if it breaks,
+    /// `end` is inclusive, `None` for the end of the resource
+    async fn receive(
+        &self,
+        host: &str,
+        origin: &str,
+        mut response: Response,
+        start: u64,
+        end: Option<u64>,
+        mut push: impl FnMut(Bytes),
+    ) -> Result<(), DownloadError> {
+        let mut position = start;
+        let mut retry = 0;
+        loop {
+            match self.with_timeout(response.chunk()).await {
+                Ok(Some(chunk)) => {
+                    position += chunk.len() as u64;
+                    push(chunk);
+                }
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    retry = self.wait_retry(host, e, retry).await?;
+                    if end.is_some_and(|end| position > end) {
+                        return Ok(());
+                    }
+
+                    // resume from where it broke
+                    response = self.send_range(host, origin, position, end).await?;
+                }
+            }
+        }
+    }
+}
+
+/// A `Content-Range` header, like `bytes 0-1023/4096`
+#[derive(Debug, PartialEq, Eq)]
+struct ContentRange {
+    start: u64,
+    /// Inclusive
+    end: u64,
+    /// `None` if the server doesn't know
+    total: Option<u64>,
+}
+
+impl ContentRange {
+    fn parse(value: &str) -> Option<Self> {
+        let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+        let (start, end) = range.split_once('-')?;
+        let range = Self {
+            start: start.trim().parse().ok()?,
+            end: end.trim().parse().ok()?,
+            total: match total.trim() {
+                "*" => None,
+                total => Some(total.parse().ok()?),
+            },
+        };
+        (range.start <= range.end && range.total.is_none_or(|x| range.end < x)).then_some(range)
+    }
+
+    fn from_headers(headers: &header::HeaderMap) -> Option<Self> {
+        Self::parse(headers.get(header::CONTENT_RANGE)?.to_str().ok()?)
+    }
+}
+
+impl Display for ContentRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.total {
+            Some(total) => write!(f, "bytes {}-{}/{}", self.start, self.end, total),
+            None => write!(f, "bytes {}-{}/*", self.start, self.end),
+        }
+    }
+}
+
+/// The first range requested by a multi-threaded download, to learn the size of the resource
+const PROBE_SIZE: u64 = 256 * 1024;
+
 impl Downloader {
-    pub fn new(http_client: Client, default_threads: u8) -> Self {
+    pub fn new(http_client: Client, default_threads: u8, policy: FailurePolicy) -> Self {
         Downloader {
-            http_client,
+            requester: Requester {
+                http_client,
+                breakers: Arc::new(CircuitBreakers::new(policy.circuit_breaker.clone())),
+                policy: Arc::new(policy),
+            },
             default_threads,
         }
     }
@@ -25,141 +228,186 @@ impl Downloader {
         self.default_threads
     }
 
-    async fn download_single_thread(
-        &self,
-        origin: impl AsRef<str>,
-    ) -> Result<(Arc<[u8]>, String), DownloadError> {
-        let response = self.http_client.get(origin.as_ref()).send().await?;
-        if !response.status().is_success() {
-            return Err(DownloadError::RequestNotSuccess(response.status().as_u16()));
-        }
-        let content_type = response
-            .headers()
+    fn get_content_type(headers: &header::HeaderMap) -> String {
+        headers
             .get(header::CONTENT_TYPE)
             .map(|x| x.to_str().unwrap_or("application/octet-stream"))
             .unwrap_or("application/octet-stream")
-            .to_owned();
-
-        let bytes = response.bytes().await?;
+            .to_owned()
+    }
 
-        return Ok((Arc::from(bytes.as_ref()), content_type));
+    /// The circuit breakers are kept by host and port
+    fn get_host(origin: &str) -> String {
+        Url::parse(origin)
+            .ok()
+            .and_then(|x| {
+                let host = x.host_str()?;
+                Some(match x.port_or_known_default() {
+                    Some(port) => format!("{}:{}", host, port),
+                    None => host.to_owned(),
+                })
+            })
+            .unwrap_or_else(|| origin.to_owned())
     }
 
-    async fn download_range(
-        http_client: Client,
-        origin: impl AsRef<str>,
-        start: u64,
-        end: u64,
-    ) -> Result<(u64, bytes::Bytes), DownloadError> {
-        let range_header = format!("bytes={}-{}", start, end);
-        let response = http_client
-            .get(origin.as_ref())
-            .header(header::RANGE, range_header)
-            .send()
+    async fn download_single_thread(
+        &self,
+        origin: &str,
+        buffer: &SharedBuffer,
+    ) -> Result<(), DownloadError> {
+        let requester = &self.requester;
+        let host = Self::get_host(origin);
+        let response = requester
+            .send(&host, || requester.http_client.get(origin))
             .await?;
-
-        if !response.status().is_success() {
-            return Err(DownloadError::RequestNotSuccess(response.status().as_u16()));
-        }
-
-        let bytes = response.bytes().await?;
-        Ok((start, bytes))
+        buffer.set_freshness(Freshness::from_headers(response.headers()));
+        buffer.set_head(
+            Self::get_content_type(response.headers()),
+            response.content_length(),
+        );
+
+        // readers can go on with every chunk
+        requester
+            .receive(&host, origin, response, 0, None, |x| buffer.push(x))
+            .await
     }
 
+    /// Download the resource into the buffer, the buffer is not finished here,
+    /// it is up to the caller
     pub async fn download(
         &self,
         origin: impl AsRef<str>,
         threads: Option<u8>,
-    ) -> Result<(Arc<[u8]>, String), DownloadError> {
+        buffer: &SharedBuffer,
+    ) -> Result<(), DownloadError> {
         let threads = threads.unwrap_or(self.default_threads);
         let origin = origin.as_ref();
 
         if threads <= 1 {
             // Fallback to single-threaded download if only one thread is requested
-            return self.download_single_thread(origin).await;
+            return self.download_single_thread(origin, buffer).await;
         }
 
-        // Step 1: Get Content-Length
-        let head_response = self.http_client.head(origin).send().await?;
-        if !head_response.status().is_success() {
-            return Err(DownloadError::RequestNotSuccess(
-                head_response.status().as_u16(),
-            ));
+        // the first range tells the size, and if ranges are supported at all
+        let requester = &self.requester;
+        let host = Self::get_host(origin);
+        let probe_range = format!("bytes=0-{}", PROBE_SIZE - 1);
+        let probe = requester
+            .send(&host, || {
+                requester
+                    .http_client
+                    .get(origin)
+                    .header(header::RANGE, probe_range.as_str())
+            })
+            .await
+            .map_err(|e| match e {
+                // an empty resource has no range at all
+                DownloadError::RequestNotSuccess(416) => DownloadError::RangeNotSupported,
+                e => e,
+            })?;
+
+        let content_type = Self::get_content_type(probe.headers());
+        buffer.set_freshness(Freshness::from_headers(probe.headers()));
+
+        // the range is ignored, and the whole resource is coming
+        if probe.status() != StatusCode::PARTIAL_CONTENT {
+            debug!(
+                "Range ignored by the server, downloading {} at once",
+                origin
+            );
+            buffer.set_head(content_type, probe.content_length());
+            return requester
+                .receive(&host, origin, probe, 0, None, |x| buffer.push(x))
+                .await;
         }
 
-        let content_length = head_response
-            .headers()
-            .get(header::CONTENT_LENGTH)
-            .and_then(|ct_len| ct_len.to_str().ok())
-            .and_then(|ct_len| ct_len.parse::<u64>().ok())
+        let probe_range =
+            ContentRange::from_headers(probe.headers()).ok_or(DownloadError::RangeNotSupported)?;
+        let content_length = probe_range
+            .total
             .ok_or(DownloadError::ContentLengthMissing)?;
-
-        // Check if server supports range requests
-        if !head_response.headers().contains_key(header::ACCEPT_RANGES) {
-            // Some servers might not explicitly send Accept-Ranges but still support it.
-            // For simplicity, we'll assume it's not supported if the header is absent.
-            // A more robust solution might involve trying a small range request first.
-            // For now, if num_threads > 1 and Accept-Ranges is missing, we'll error out.
-            if threads > 1 {
-                return Err(DownloadError::RangeNotSupported);
-            }
+        if probe_range.start != 0 {
+            return Err(DownloadError::RangeMismatch(
+                format!("bytes=0-{}", PROBE_SIZE - 1),
+                probe_range.to_string(),
+            ));
         }
 
-        let content_type = head_response
-            .headers()
-            .get(header::CONTENT_TYPE)
-            .map(|x| x.to_str().unwrap_or("application/octet-stream").to_owned())
-            .unwrap_or("application/octet-stream".to_string());
-
-        let chunk_size = content_length / threads as u64;
-        let mut tasks = Vec::with_capacity(threads.into());
+        // the rest is split between the other threads
+        let rest_start = probe_range.end + 1;
+        let rest_length = content_length - rest_start;
+        let parts = (threads as u64 - 1).min(rest_length);
+        let mut tasks = PartTasks(Vec::with_capacity(parts as usize));
 
-        for i in 0..threads {
-            let start = i as u64 * chunk_size;
-            let end = if i == threads - 1 {
+        for i in 0..parts {
+            let start = rest_start + i * (rest_length / parts);
+            let end = if i == parts - 1 {
                 content_length - 1
             } else {
-                start + chunk_size - 1
+                start + rest_length / parts - 1
             };
-            let client_clone = self.http_client.clone();
-            let origin_clone = origin.to_string();
-
-            tasks.push(tokio::spawn(async move {
-                Self::download_range(client_clone, origin_clone, start, end).await
-            }));
-        }
-
-        let results = join_all(tasks).await;
-
-        let mut downloaded_parts: Vec<(u64, bytes::Bytes)> = Vec::with_capacity(threads.into());
-        for res in results {
-            match res {
-                Ok(Ok(part)) => downloaded_parts.push(part),
-                Ok(Err(e)) => return Err(e), // Propagate download errors
-                Err(_) => return Err(DownloadError::ReassemblyError), // Task join error
-            }
+            let requester = requester.clone();
+            let host = host.clone();
+            let origin = origin.to_string();
+
+            // collected in background
+            tasks.0.push((
+                start,
+                tokio::spawn(async move {
+                    let response = requester
+                        .send_range(&host, &origin, start, Some(end))
+                        .await?;
+                    let mut bytes = BytesMut::new();
+                    requester
+                        .receive(&host, &origin, response, start, Some(end), |x| {
+                            bytes.extend_from_slice(&x)
+                        })
+                        .await?;
+                    Ok::<_, DownloadError>(bytes.freeze())
+                }),
+            ));
         }
 
-        // Sort parts by their starting byte to reassemble correctly
-        downloaded_parts.sort_by_key(|(start, _)| *start);
+        buffer.set_head(content_type, Some(content_length));
 
-        let mut buffer = BytesMut::with_capacity(content_length as usize);
-        let mut current_pos = 0;
+        // the probe is streamed
+        requester
+            .receive(&host, origin, probe, 0, Some(probe_range.end), |x| {
+                buffer.push(x)
+            })
+            .await?;
 
-        for (start, bytes) in downloaded_parts {
-            if start != current_pos {
+        // append the parts in order, a part is awaited after all bytes before it are pushed
+        for (start, task) in tasks.0.iter_mut() {
+            if *start != buffer.len() as u64 {
                 // This indicates a gap or out-of-order chunk, which is an error in reassembly
                 return Err(DownloadError::ReassemblyError);
             }
-            current_pos += bytes.len() as u64;
-            buffer.put(bytes);
+
+            match task.await {
+                Ok(Ok(bytes)) => buffer.push(bytes),
+                Ok(Err(e)) => return Err(e), // Propagate download errors
+                Err(_) => return Err(DownloadError::ReassemblyError), // Task join error
+            }
         }
 
-        if current_pos != content_length {
+        if buffer.len() as u64 != content_length {
             return Err(DownloadError::ReassemblyError);
         }
 
-        Ok((Arc::from(buffer.freeze().as_ref()), content_type))
+        Ok(())
+    }
+}
+
+/// The parts of a download collected in background, aborted once it ends,
+/// so those left are not downloaded for nothing when it fails early
+struct PartTasks(Vec<(u64, JoinHandle<Result<Bytes, DownloadError>>)>);
+
+impl Drop for PartTasks {
+    fn drop(&mut self) {
+        for (_, task) in self.0.iter() {
+            task.abort();
+        }
     }
 }
 
@@ -169,22 +417,43 @@ pub enum DownloadError {
     RequestNotSuccess(u16),
     ContentLengthMissing,
     RangeNotSupported,
+    /// A range response for another range than requested, with the requested and the received
+    RangeMismatch(String, String),
     ReassemblyError,
+    /// No response or no more bytes within the attempt timeout
+    Timeout,
+    /// Requests to the host fail fast, as it failed too many times in a row
+    CircuitOpen(String),
+    /// Rejected by a validator
+    InvalidContent(String),
 }
 
 impl DownloadError {
-    pub fn is_range_not_supported(&self) -> bool {
+    /// A short name of the variant, used as a metrics label
+    pub fn kind(&self) -> &'static str {
         match self {
-            Self::RangeNotSupported => true,
-            _ => false,
+            Self::RequestError(_) => "request_error",
+            Self::RequestNotSuccess(_) => "request_not_success",
+            Self::ContentLengthMissing => "content_length_missing",
+            Self::RangeNotSupported => "range_not_supported",
+            Self::RangeMismatch(_, _) => "range_mismatch",
+            Self::ReassemblyError => "reassembly_error",
+            Self::Timeout => "timeout",
+            Self::CircuitOpen(_) => "circuit_open",
+            Self::InvalidContent(_) => "invalid_content",
         }
     }
 
+    pub fn is_range_not_supported(&self) -> bool {
+        matches!(self, Self::RangeNotSupported | Self::RangeMismatch(_, _))
+    }
+
     pub fn is_content_length_missing(&self) -> bool {
-        match self {
-            Self::ContentLengthMissing => true,
-            _ => false,
-        }
+        matches!(self, Self::ContentLengthMissing)
+    }
+
+    pub fn is_circuit_open(&self) -> bool {
+        matches!(self, Self::CircuitOpen(_))
     }
 }
 
@@ -197,7 +466,13 @@ impl Display for DownloadError {
             }
             Self::ContentLengthMissing => write!(f, "Content-Length header is missing"),
             Self::RangeNotSupported => write!(f, "Server does not support range requests"),
+            Self::RangeMismatch(requested, received) => {
+                write!(f, "Requested {} but received {}", requested, received)
+            }
             Self::ReassemblyError => write!(f, "Error reassembling downloaded chunks"),
+            Self::Timeout => write!(f, "Timed out waiting for the server"),
+            Self::CircuitOpen(host) => write!(f, "Circuit of {} is open, failing fast", host),
+            Self::InvalidContent(reason) => write!(f, "Invalid content: {}", reason),
         }
     }
 }
@@ -209,3 +484,139 @@ impl From<reqwest::Error> for DownloadError {
         Self::RequestError(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        http::{HeaderMap, StatusCode, header},
+        response::{IntoResponse, Response},
+        routing::get,
+    };
+    use bytes::Bytes;
+    use reqwest::Client;
+    use tokio::{net::TcpListener, runtime::Runtime};
+
+    use crate::caching::{
+        DownloadError, Downloader, FailurePolicy, RequestRetries, SharedBuffer,
+        download::{ContentRange, PROBE_SIZE},
+    };
+
+    fn create_body(len: u64) -> Bytes {
+        (0..len).map(|x| (x % 251) as u8).collect::<Vec<_>>().into()
+    }
+
+    /// Answers `bytes=a-b` ranges of `body`, and the whole body without one
+    fn serve_range(headers: &HeaderMap, body: Bytes) -> Response {
+        let range = headers
+            .get(header::RANGE)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.strip_prefix("bytes="))
+            .and_then(|x| x.split_once('-'));
+        let Some((start, end)) = range else {
+            return body.into_response();
+        };
+
+        let start = start.parse::<usize>().unwrap();
+        let end = end
+            .parse::<usize>()
+            .map_or(body.len(), |x| x + 1)
+            .min(body.len());
+        let content_range = format!("bytes {}-{}/{}", start, end - 1, body.len());
+        (
+            StatusCode::PARTIAL_CONTENT,
+            [(header::CONTENT_RANGE, content_range)],
+            body.slice(start..end),
+        )
+            .into_response()
+    }
+
+    /// Download `path` from a local origin with 3 threads
+    fn download(path: &str) -> (Result<(), DownloadError>, Bytes) {
+        let router = Router::new()
+            .route(
+                "/ranged",
+                get(|headers: HeaderMap| async move {
+                    serve_range(&headers, create_body(PROBE_SIZE * 3 + 1000))
+                }),
+            )
+            .route(
+                "/short",
+                get(|headers: HeaderMap| async move { serve_range(&headers, create_body(1000)) }),
+            )
+            .route(
+                "/no-ranges",
+                get(|| async { create_body(PROBE_SIZE + 1000) }),
+            )
+            .route(
+                "/wrong-range",
+                get(|| async {
+                    (
+                        StatusCode::PARTIAL_CONTENT,
+                        [(header::CONTENT_RANGE, "bytes 100-199/1000")],
+                        create_body(100),
+                    )
+                }),
+            );
+        let policy = FailurePolicy {
+            retries: 0,
+            request_retries: RequestRetries {
+                server_error: 0,
+                timeout: 0,
+                other: 0,
+            },
+            ..Default::default()
+        };
+        let downloader = Downloader::new(Client::new(), 3, policy);
+
+        Runtime::new().unwrap().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let origin = format!("http://{}{}", listener.local_addr().unwrap(), path);
+            tokio::spawn(async move { axum::serve(listener, router).await });
+
+            let buffer = SharedBuffer::new();
+            let result = downloader.download(origin, None, &buffer).await;
+            (result, buffer.get_chunks().concat().into())
+        })
+    }
+
+    #[test]
+    fn test_download() {
+        let (result, bytes) = download("/ranged");
+        assert!(result.is_ok());
+        assert_eq!(bytes, create_body(PROBE_SIZE * 3 + 1000));
+
+        // all in the probe
+        let (result, bytes) = download("/short");
+        assert!(result.is_ok());
+        assert_eq!(bytes, create_body(1000));
+
+        // the whole body comes with the probe
+        let (result, bytes) = download("/no-ranges");
+        assert!(result.is_ok());
+        assert_eq!(bytes, create_body(PROBE_SIZE + 1000));
+
+        let (result, bytes) = download("/wrong-range");
+        assert!(matches!(result, Err(DownloadError::RangeMismatch(..))));
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn test_content_range() {
+        assert_eq!(
+            ContentRange::parse("bytes 0-1023/4096"),
+            Some(ContentRange {
+                start: 0,
+                end: 1023,
+                total: Some(4096),
+            })
+        );
+        assert_eq!(
+            ContentRange::parse("bytes 100-199/*").unwrap().to_string(),
+            "bytes 100-199/*"
+        );
+        assert_eq!(ContentRange::parse("bytes 0-4096/4096"), None);
+        assert_eq!(ContentRange::parse("bytes */4096"), None);
+        assert_eq!(ContentRange::parse("items 0-1/2"), None);
+    }
+}
@@ -0,0 +1,119 @@
+use std::{cmp::Ordering, time::Instant};
+
+use serde::Deserialize;
+
+/// Which items are dropped first when the cache pool is full
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EvictionPolicy {
+    /// Least recently used
+    #[default]
+    Lru,
+    /// Least frequently used, ties are broken by recency
+    Lfu,
+    /// Greedy-Dual-Size-Frequency, prefers keeping small and popular items,
+    /// and ages out items that were popular long ago
+    Gdsf,
+}
+
+/// How an item has been accessed, kept by each cache item
+#[derive(Clone, Debug)]
+pub struct AccessStats {
+    last_access: Instant,
+    hits: u64,
+    /// The pool clock when last accessed, only used by GDSF
+    clock: f64,
+}
+
+impl AccessStats {
+    pub fn new(clock: f64) -> Self {
+        Self {
+            last_access: Instant::now(),
+            hits: 0,
+            clock,
+        }
+    }
+
+    pub fn touch(&mut self, clock: f64) {
+        self.last_access = Instant::now();
+        self.hits += 1;
+        self.clock = clock;
+    }
+}
+
+impl EvictionPolicy {
+    /// The priority of an item, lower ones are evicted first.
+    /// For GDSF, the priority of the evicted item becomes the new pool clock.
+    pub fn priority(&self, stats: &AccessStats, size: usize, now: Instant) -> f64 {
+        match self {
+            Self::Lru => -now.duration_since(stats.last_access).as_secs_f64(),
+            Self::Lfu => stats.hits as f64,
+            Self::Gdsf => stats.clock + (stats.hits + 1) as f64 / size.max(1) as f64,
+        }
+    }
+
+    /// Compare two items, the one to be evicted first is the less one
+    pub fn compare(
+        &self,
+        a: (&AccessStats, usize),
+        b: (&AccessStats, usize),
+        now: Instant,
+    ) -> Ordering {
+        self.priority(a.0, a.1, now)
+            .total_cmp(&self.priority(b.0, b.1, now))
+            .then(a.0.last_access.cmp(&b.0.last_access))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cmp::Ordering,
+        time::{Duration, Instant},
+    };
+
+    use crate::caching::{AccessStats, EvictionPolicy};
+
+    #[test]
+    fn test_lru_and_lfu() {
+        let mut old = AccessStats::new(0.0);
+        old.touch(0.0);
+        old.touch(0.0);
+        std::thread::sleep(Duration::from_millis(5));
+        let mut recent = AccessStats::new(0.0);
+        recent.touch(0.0);
+        let now = Instant::now();
+
+        assert_eq!(
+            EvictionPolicy::Lru.compare((&old, 1), (&recent, 1), now),
+            Ordering::Less
+        );
+        assert_eq!(
+            EvictionPolicy::Lfu.compare((&old, 1), (&recent, 1), now),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn test_gdsf() {
+        let now = Instant::now();
+        let mut small = AccessStats::new(0.0);
+        small.touch(0.0);
+        let mut large = AccessStats::new(0.0);
+        large.touch(0.0);
+
+        // same popularity, the larger one goes first
+        assert_eq!(
+            EvictionPolicy::Gdsf.compare((&large, 4096), (&small, 1024), now),
+            Ordering::Less
+        );
+
+        // an item accessed after the clock advanced outlives the old ones
+        let mut aged = AccessStats::new(0.0);
+        aged.touch(1.0);
+        assert_eq!(
+            EvictionPolicy::Gdsf.compare((&small, 1024), (&aged, 4096), now),
+            Ordering::Less
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::caching::{CircuitBreakerConfig, DownloadError};
+
+/// What kind of failure a download ended with, decides whether to retry
+/// and how long the failure is remembered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureClass {
+    /// The origin responded 404 or 410
+    NotFound,
+    /// Any other 4xx
+    ClientError,
+    /// 5xx
+    ServerError,
+    /// The origin didn't respond in time
+    Timeout,
+    /// Connection errors, broken responses...
+    Other,
+}
+
+impl FailureClass {
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, Self::NotFound | Self::ClientError)
+    }
+}
+
+impl DownloadError {
+    pub fn class(&self) -> FailureClass {
+        match self {
+            Self::RequestNotSuccess(404 | 410) => FailureClass::NotFound,
+            Self::RequestNotSuccess(400..=499) => FailureClass::ClientError,
+            Self::RequestNotSuccess(500..=599) => FailureClass::ServerError,
+            Self::RequestError(e) if e.is_timeout() => FailureClass::Timeout,
+            Self::Timeout => FailureClass::Timeout,
+            Self::CircuitOpen(_) => FailureClass::ServerError,
+            _ => FailureClass::Other,
+        }
+    }
+
+    /// The status sent to clients, the one of the origin if it responded with an error
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::RequestNotSuccess(status @ 400..=599) => *status,
+            Self::RequestError(e) if e.is_timeout() => 504,
+            Self::Timeout => 504,
+            Self::CircuitOpen(_) => 503,
+            _ => 502,
+        }
+    }
+
+    /// The origin failed to respond, rather than responded with something unusable,
+    /// counted by the circuit breaker and worth sending the request again
+    pub fn is_origin_failure(&self) -> bool {
+        matches!(
+            self,
+            Self::RequestError(_) | Self::RequestNotSuccess(500..=599) | Self::Timeout
+        )
+    }
+}
+
+/// How failed downloads are retried and remembered
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FailurePolicy {
+    /// Retries of the whole download after the first attempt,
+    /// a failed request is retried by `request_retries` before
+    pub retries: u32,
+    /// Retries of each request of a download, by the class of its failure,
+    /// a broken body is resumed from where it broke
+    pub request_retries: RequestRetries,
+    /// Delay before the first retry (in milliseconds), doubled on each retry
+    pub backoff: u64,
+    /// Upper bound of the delay (in milliseconds)
+    pub max_backoff: u64,
+    /// The random part of each delay, from 0 (none) to 1 (anywhere up to the full delay)
+    pub jitter: f64,
+    /// How long a request may wait for the response, or for the next bytes of its body
+    /// (in milliseconds), 0 for no limit
+    pub attempt_timeout: u64,
+    /// How long a download may take with all its retries (in milliseconds), 0 for no limit,
+    /// the retries alone allow up to `(retries + 1) * (request_retries + 1)` attempts
+    pub load_timeout: u64,
+    pub circuit_breaker: CircuitBreakerConfig,
+    pub negative_ttl: NegativeTtl,
+    /// How long an expired segment or playlist is kept (in seconds), and served
+    /// if the origin fails to give a new one with a retryable error, 0 to disable
+    pub stale_if_error: u64,
+}
+
+/// Retries of a request, for the classes of failures worth retrying
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RequestRetries {
+    pub server_error: u32,
+    pub timeout: u32,
+    pub other: u32,
+}
+
+impl Default for RequestRetries {
+    fn default() -> Self {
+        Self {
+            server_error: 2,
+            timeout: 1,
+            other: 3,
+        }
+    }
+}
+
+/// How long a failure is served from the cache before trying the origin again (in seconds)
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NegativeTtl {
+    pub not_found: u64,
+    pub client_error: u64,
+    pub server_error: u64,
+    pub timeout: u64,
+    pub other: u64,
+}
+
+impl Default for FailurePolicy {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            request_retries: RequestRetries::default(),
+            backoff: 200,
+            max_backoff: 2000,
+            jitter: 0.5,
+            attempt_timeout: 10000,
+            load_timeout: 30000,
+            circuit_breaker: CircuitBreakerConfig::default(),
+            negative_ttl: NegativeTtl::default(),
+            stale_if_error: 30,
+        }
+    }
+}
+
+impl Default for NegativeTtl {
+    fn default() -> Self {
+        Self {
+            not_found: 10,
+            client_error: 5,
+            server_error: 2,
+            timeout: 1,
+            other: 2,
+        }
+    }
+}
+
+impl FailurePolicy {
+    /// The delay before the given retry (starts from 0)
+    pub fn get_backoff(&self, retry: u32) -> Duration {
+        let backoff = self.backoff.saturating_mul(1 << retry.min(16));
+        Duration::from_millis(backoff.min(self.max_backoff))
+    }
+
+    /// The delay before the given retry, shortened by a random part so retries
+    /// of many clients don't hit the origin at the same time
+    pub fn get_jittered_backoff(&self, retry: u32) -> Duration {
+        self.get_backoff(retry)
+            .mul_f64(1.0 - self.jitter.clamp(0.0, 1.0) * fastrand::f64())
+    }
+
+    pub fn get_request_retries(&self, class: FailureClass) -> u32 {
+        let retries = &self.request_retries;
+        match class {
+            FailureClass::NotFound | FailureClass::ClientError => 0,
+            FailureClass::ServerError => retries.server_error,
+            FailureClass::Timeout => retries.timeout,
+            FailureClass::Other => retries.other,
+        }
+    }
+
+    /// `None` for no limit
+    pub fn get_attempt_timeout(&self) -> Option<Duration> {
+        (self.attempt_timeout > 0).then(|| Duration::from_millis(self.attempt_timeout))
+    }
+
+    /// `None` for no limit
+    pub fn get_load_timeout(&self) -> Option<Duration> {
+        (self.load_timeout > 0).then(|| Duration::from_millis(self.load_timeout))
+    }
+
+    pub fn get_negative_ttl(&self, class: FailureClass) -> Duration {
+        let ttl = &self.negative_ttl;
+        Duration::from_secs(match class {
+            FailureClass::NotFound => ttl.not_found,
+            FailureClass::ClientError => ttl.client_error,
+            FailureClass::ServerError => ttl.server_error,
+            FailureClass::Timeout => ttl.timeout,
+            FailureClass::Other => ttl.other,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::caching::{DownloadError, FailureClass, FailurePolicy};
+
+    #[test]
+    fn test_classify() {
+        let not_found = DownloadError::RequestNotSuccess(404);
+        assert_eq!(not_found.class(), FailureClass::NotFound);
+        assert_eq!(not_found.status(), 404);
+        assert!(!not_found.class().is_retryable());
+
+        let server_error = DownloadError::RequestNotSuccess(503);
+        assert_eq!(server_error.class(), FailureClass::ServerError);
+        assert!(server_error.class().is_retryable());
+
+        let broken = DownloadError::ReassemblyError;
+        assert_eq!(broken.class(), FailureClass::Other);
+        assert_eq!(broken.status(), 502);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = FailurePolicy::default();
+        assert_eq!(policy.get_backoff(0), Duration::from_millis(200));
+        assert_eq!(policy.get_backoff(2), Duration::from_millis(800));
+        assert_eq!(policy.get_backoff(10), Duration::from_millis(2000));
+
+        for retry in 0..4 {
+            let backoff = policy.get_jittered_backoff(retry);
+            assert!(backoff <= policy.get_backoff(retry));
+            assert!(backoff >= policy.get_backoff(retry) / 2);
+        }
+    }
+}
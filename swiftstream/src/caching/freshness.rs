@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{self, HeaderMap};
+use serde::Deserialize;
+
+/// What the origin says about caching a response
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Freshness {
+    pub no_store: bool,
+    pub immutable: bool,
+    /// How long the response stays fresh, with its `Age` deducted
+    pub max_age: Option<Duration>,
+}
+
+impl Freshness {
+    /// Read `Cache-Control`, `Expires` and `Age`, `s-maxage` takes precedence over `max-age`,
+    /// and both over `Expires`
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut freshness = Self::default();
+        let mut max_age = None;
+        let mut shared_max_age = None;
+
+        let directives = headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|x| x.to_str().ok())
+            .flat_map(|x| x.split(','));
+        for directive in directives {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name, Some(value.trim().trim_matches('"'))),
+                None => (directive, None),
+            };
+            match name.trim().to_ascii_lowercase().as_str() {
+                "no-store" => freshness.no_store = true,
+                // asks to revalidate before reuse, segments don't change once published,
+                // so it neither shortens the TTL nor means not to keep them
+                "no-cache" => {}
+                "immutable" => freshness.immutable = true,
+                "max-age" => max_age = value.and_then(|x| x.parse().ok()).or(max_age),
+                "s-maxage" => shared_max_age = value.and_then(|x| x.parse::<u64>().ok()),
+                _ => {}
+            }
+        }
+
+        let max_age = shared_max_age
+            .or(max_age)
+            .map(Duration::from_secs)
+            .or_else(|| Self::get_expires(headers));
+        let age = get_header(headers, header::AGE)
+            .and_then(|x| x.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_default();
+        freshness.max_age = max_age.map(|x| x.saturating_sub(age));
+
+        freshness
+    }
+
+    /// `Expires` relative to `Date`, or to now if the origin sent no date,
+    /// an invalid date means already expired
+    fn get_expires(headers: &HeaderMap) -> Option<Duration> {
+        let expires = get_header(headers, header::EXPIRES)?;
+        let Some(expires) = parse_http_date(expires) else {
+            return Some(Duration::ZERO);
+        };
+        let date = get_header(headers, header::DATE)
+            .and_then(parse_http_date)
+            .unwrap_or_else(Utc::now);
+        Some((expires - date).to_std().unwrap_or_default())
+    }
+}
+
+fn get_header(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|x| x.to_str().ok())
+}
+
+fn parse_http_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(date)
+        .ok()
+        .map(|x| x.with_timezone(&Utc))
+}
+
+/// How long segments are kept by what their origin says (in seconds)
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TtlPolicy {
+    /// Honour `Cache-Control`, `Expires` and `Age`, segments without them are kept
+    /// for `cacheExpire` after their last access
+    pub respect_headers: bool,
+    /// The shortest TTL, so a live segment with `max-age=0` still serves the viewers
+    /// arriving right after its download, not applied to `no-store`
+    pub min_ttl: u64,
+    /// The longest TTL, also the TTL of `immutable` segments
+    pub max_ttl: u64,
+}
+
+impl Default for TtlPolicy {
+    fn default() -> Self {
+        Self {
+            respect_headers: true,
+            min_ttl: 10,
+            max_ttl: 24 * 3600,
+        }
+    }
+}
+
+impl TtlPolicy {
+    /// The TTL of a downloaded segment, `None` if the origin didn't tell
+    pub fn get_ttl(&self, freshness: &Freshness) -> Option<Duration> {
+        if !self.respect_headers {
+            return None;
+        }
+        if freshness.no_store {
+            return Some(Duration::ZERO);
+        }
+
+        let min_ttl = Duration::from_secs(self.min_ttl);
+        let max_ttl = Duration::from_secs(self.max_ttl).max(min_ttl);
+        if freshness.immutable {
+            return Some(max_ttl);
+        }
+        freshness.max_age.map(|x| x.clamp(min_ttl, max_ttl))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::header::{self, HeaderMap, HeaderValue};
+
+    use crate::caching::{Freshness, TtlPolicy};
+
+    fn parse(headers: &[(header::HeaderName, &'static str)]) -> Freshness {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(name, HeaderValue::from_static(value));
+        }
+        Freshness::from_headers(&map)
+    }
+
+    #[test]
+    fn test_freshness() {
+        let freshness = parse(&[
+            (header::CACHE_CONTROL, "public, max-age=600"),
+            (header::AGE, "100"),
+        ]);
+        assert_eq!(freshness.max_age, Some(Duration::from_secs(500)));
+
+        let freshness = parse(&[(header::CACHE_CONTROL, "max-age=600, s-maxage=60")]);
+        assert_eq!(freshness.max_age, Some(Duration::from_secs(60)));
+
+        let freshness = parse(&[
+            (header::DATE, "Sun, 18 Oct 2026 10:00:00 GMT"),
+            (header::EXPIRES, "Sun, 18 Oct 2026 11:00:00 GMT"),
+        ]);
+        assert_eq!(freshness.max_age, Some(Duration::from_secs(3600)));
+
+        let freshness = parse(&[(header::EXPIRES, "0")]);
+        assert_eq!(freshness.max_age, Some(Duration::ZERO));
+
+        assert!(parse(&[(header::CACHE_CONTROL, "no-store")]).no_store);
+        let freshness = parse(&[(header::CACHE_CONTROL, "no-cache, max-age=60")]);
+        assert_eq!(freshness.max_age, Some(Duration::from_secs(60)));
+        assert_eq!(parse(&[(header::CACHE_CONTROL, "no-cache")]).max_age, None);
+        assert_eq!(parse(&[]), Freshness::default());
+    }
+
+    #[test]
+    fn test_ttl() {
+        let policy = TtlPolicy {
+            respect_headers: true,
+            min_ttl: 5,
+            max_ttl: 3600,
+        };
+        let get_ttl = |freshness: Freshness| policy.get_ttl(&freshness).map(|x| x.as_secs());
+
+        assert_eq!(get_ttl(Freshness::default()), None);
+        assert_eq!(
+            get_ttl(Freshness {
+                max_age: Some(Duration::from_secs(86400)),
+                ..Default::default()
+            }),
+            Some(3600)
+        );
+        assert_eq!(
+            get_ttl(Freshness {
+                max_age: Some(Duration::ZERO),
+                ..Default::default()
+            }),
+            Some(5)
+        );
+        assert_eq!(
+            get_ttl(Freshness {
+                no_store: true,
+                max_age: Some(Duration::from_secs(60)),
+                ..Default::default()
+            }),
+            Some(0)
+        );
+        assert_eq!(
+            get_ttl(Freshness {
+                immutable: true,
+                ..Default::default()
+            }),
+            Some(3600)
+        );
+    }
+}
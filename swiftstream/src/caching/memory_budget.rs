@@ -0,0 +1,75 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Bytes reserved by the buffers of a pool, a buffer reserves its Content-Length
+/// once known, and grows the reservation if more data arrives
+pub struct MemoryBudget {
+    used: AtomicUsize,
+    limit: usize,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            used: AtomicUsize::new(0),
+            limit,
+        }
+    }
+
+    pub fn reserve(&self, size: usize) {
+        self.used.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub fn release(&self, size: usize) {
+        self.used.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    pub fn get_limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn get_used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.get_used() > self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+
+    use crate::caching::{MemoryBudget, SharedBuffer};
+
+    #[test]
+    fn test_buffer_reservation() {
+        let budget = Arc::new(MemoryBudget::new(8));
+        let group = Arc::new(MemoryBudget::new(4));
+        let buffer = SharedBuffer::with_budgets(vec![budget.clone(), group.clone()]);
+
+        // reserved as soon as the length is known
+        buffer.set_head("video/mp2t".into(), Some(6));
+        assert_eq!(budget.get_used(), 6);
+        assert!(group.is_exceeded());
+        buffer.push(Bytes::from_static(b"abcd"));
+        assert_eq!(budget.get_used(), 6);
+
+        // more than announced
+        buffer.push(Bytes::from_static(b"efgh"));
+        assert_eq!(budget.get_used(), 8);
+        assert!(!budget.is_exceeded());
+
+        buffer.fail(503);
+        assert_eq!(budget.get_used(), 0);
+        assert_eq!(group.get_used(), 0);
+
+        let buffer = SharedBuffer::with_budgets(vec![budget.clone()]);
+        buffer.push(Bytes::from_static(b"abcdefghij"));
+        assert!(budget.is_exceeded());
+        drop(buffer);
+        assert_eq!(budget.get_used(), 0);
+    }
+}
@@ -1,6 +1,36 @@
+mod cache_key;
 mod cache_pool;
+mod cache_store;
+mod circuit_breaker;
+mod content_store;
+mod disk_cache;
 mod download;
+mod eviction;
+mod failure;
+mod freshness;
+mod memory_budget;
+mod pinning;
+mod quota;
+mod shared_buffer;
+mod sniffing;
+mod stale;
 mod stream_tracking;
+mod validation;
+pub use cache_key::*;
 pub use cache_pool::*;
+pub use cache_store::*;
+pub use circuit_breaker::*;
+pub use content_store::*;
+pub use disk_cache::*;
 pub use download::*;
+pub use eviction::*;
+pub use failure::*;
+pub use freshness::*;
+pub use memory_budget::*;
+pub use pinning::*;
+pub use quota::*;
+pub use shared_buffer::*;
+pub use sniffing::*;
+pub use stale::*;
 pub use stream_tracking::*;
+pub use validation::*;
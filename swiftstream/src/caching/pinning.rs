@@ -0,0 +1,250 @@
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+use cron::Schedule;
+use log::{debug, warn};
+use mediastream_rs::format::attributes::TVG_ID;
+use reqwest::Client;
+use serde::{Deserialize, Deserializer};
+use tokio::{sync::RwLock, time::sleep};
+use url::Url;
+
+use crate::{
+    caching::{CachePool, StreamTrackingPool},
+    transfer::parse_m3u8_async,
+};
+
+/// Channels tracked without anyone watching, so they start instantly
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PinningConfig {
+    /// The playlist `tvgId`s are looked up in
+    pub source: Option<String>,
+    pub channels: Vec<PinnedChannel>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PinnedChannel {
+    /// The media playlist to track, or
+    pub origin: Option<String>,
+    /// the `tvg-id` of the channel in the source playlist
+    pub tvg_id: Option<String>,
+    /// Track only in the windows starting at each time the schedule fires,
+    /// tracked all the time if not set
+    #[serde(default, deserialize_with = "deserialize_schedule")]
+    pub schedule: Option<Schedule>,
+    /// The length of each window (in seconds)
+    #[serde(default = "default_window")]
+    pub duration: u64,
+}
+
+fn default_window() -> u64 {
+    60 * 60
+}
+
+/// Accepts the standard 5 fields, or 6 and 7 fields with seconds (and years)
+fn deserialize_schedule<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Schedule>, D::Error> {
+    let Some(expression) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    let expression = match expression.split_whitespace().count() {
+        5 => format!("0 {}", expression),
+        _ => expression,
+    };
+    Schedule::from_str(&expression)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+impl PinnedChannel {
+    /// How long the channel should be tracked from `now`, `None` if it is out of its windows
+    pub fn get_remaining(&self, now: DateTime<Local>) -> Option<Duration> {
+        let Some(schedule) = &self.schedule else {
+            return Some(Duration::MAX);
+        };
+
+        let duration = Duration::from_secs(self.duration);
+        let window_start = now - duration;
+        let fired = schedule.after(&window_start).next()?;
+        if fired > now {
+            return None;
+        }
+
+        (fired + duration - now).to_std().ok()
+    }
+}
+
+/// Keeps the pinned channels tracked, and their segments from eviction
+pub struct PinningScheduler {
+    config: PinningConfig,
+    interval: Duration,
+    tracking_pool: Arc<StreamTrackingPool>,
+    cache_pool: Arc<CachePool>,
+    http_client: Client,
+    /// `tvg-id` to the location in the source playlist, and when it is fetched
+    source: RwLock<Option<(HashMap<String, String>, Instant)>>,
+}
+
+/// How often the source playlist is fetched again
+const SOURCE_REFRESH: Duration = Duration::from_secs(10 * 60);
+
+impl PinningScheduler {
+    pub fn new(
+        config: PinningConfig,
+        interval_secs: u16,
+        tracking_pool: Arc<StreamTrackingPool>,
+        cache_pool: Arc<CachePool>,
+        http_client: Client,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            interval: Duration::from_secs(interval_secs.into()),
+            tracking_pool,
+            cache_pool,
+            http_client,
+            source: RwLock::new(None),
+        })
+    }
+
+    pub fn start(self: &Arc<Self>) {
+        let self_ref = self.clone();
+        tokio::spawn(async move {
+            loop {
+                self_ref.pin_all().await;
+                sleep(self_ref.interval).await;
+            }
+        });
+    }
+
+    async fn pin_all(&self) {
+        let now = Local::now();
+        for channel in self.config.channels.iter() {
+            let Some(remaining) = channel.get_remaining(now) else {
+                continue;
+            };
+
+            let Some(origin) = self.get_origin(channel).await else {
+                continue;
+            };
+
+            // extended on every round, and left to expire once out of the window
+            let duration = remaining.min(self.interval * 3);
+            self.tracking_pool.track_for(&origin, duration).await;
+            self.cache_pool.pin_for(&origin, duration);
+        }
+    }
+
+    async fn get_origin(&self, channel: &PinnedChannel) -> Option<String> {
+        if let Some(origin) = &channel.origin {
+            return Some(origin.clone());
+        }
+
+        let tvg_id = channel.tvg_id.as_ref()?;
+        self.refresh_source().await;
+        let origin = self
+            .source
+            .read()
+            .await
+            .as_ref()
+            .and_then(|(channels, _)| channels.get(tvg_id).cloned());
+        if origin.is_none() {
+            warn!("Pinned channel {} not found in the source playlist", tvg_id);
+        }
+        origin
+    }
+
+    async fn refresh_source(&self) {
+        let Some(source) = &self.config.source else {
+            return;
+        };
+
+        if self
+            .source
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|(_, fetched)| fetched.elapsed() < SOURCE_REFRESH)
+        {
+            return;
+        }
+
+        match self.fetch_source(source).await {
+            Ok(channels) => {
+                debug!("Source playlist {} fetched", source);
+                *self.source.write().await = Some((channels, Instant::now()));
+            }
+            Err(e) => {
+                warn!("Failed to fetch the source playlist {}: {}", source, e);
+                // keep the channels fetched before, and retry on next refresh
+                let mut source = self.source.write().await;
+                let channels = source.take().map(|x| x.0).unwrap_or_default();
+                *source = Some((channels, Instant::now()));
+            }
+        }
+    }
+
+    async fn fetch_source(&self, source: &str) -> Result<HashMap<String, String>, anyhow::Error> {
+        let data = self
+            .http_client
+            .get(source)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let playlist = parse_m3u8_async(Cursor::new(data)).await?;
+
+        let base_url = Url::parse(source)?;
+        let mut channels = HashMap::new();
+        for media in playlist.medias.iter() {
+            if let Some(tvg_id) = media.attributes.get(TVG_ID) {
+                let location = base_url.join(&media.location)?;
+                channels.insert(tvg_id.to_string(), location.to_string());
+            }
+        }
+
+        Ok(channels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{Local, TimeZone};
+
+    use crate::caching::PinnedChannel;
+
+    #[test]
+    fn test_window() {
+        let channel: PinnedChannel = serde_yaml::from_str(
+            "{ origin: 'http://a/b.m3u8', schedule: '0 20 * * *', duration: 7200 }",
+        )
+        .unwrap();
+
+        let at = |h, m| Local.with_ymd_and_hms(2025, 6, 1, h, m, 0).unwrap();
+        assert_eq!(channel.get_remaining(at(19, 59)), None);
+        assert_eq!(
+            channel.get_remaining(at(20, 0)),
+            Some(Duration::from_secs(7200))
+        );
+        assert_eq!(
+            channel.get_remaining(at(21, 30)),
+            Some(Duration::from_secs(1800))
+        );
+        assert_eq!(channel.get_remaining(at(22, 1)), None);
+
+        let channel: PinnedChannel = serde_yaml::from_str("{ tvgId: cctv1 }").unwrap();
+        assert_eq!(channel.get_remaining(at(3, 0)), Some(Duration::MAX));
+    }
+}
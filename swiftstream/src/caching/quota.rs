@@ -0,0 +1,67 @@
+use serde::Deserialize;
+use url::Url;
+
+/// How segments are grouped for quotas
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaGroup {
+    /// By the HLS stream (media playlist) the segment belongs to,
+    /// segments requested without a known stream are grouped by host
+    #[default]
+    Stream,
+    /// By the host of the segment
+    Host,
+}
+
+/// Limits how much of the pool one stream (or host) can take
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct QuotaPolicy {
+    pub group_by: QuotaGroup,
+    /// Upper bound of the bytes of one group
+    pub max_size: Option<usize>,
+    /// When the pool is full, evict from the groups using more than their fair share first,
+    /// the fair share is the size limit divided by the count of active groups
+    pub fair_share: bool,
+}
+
+impl QuotaPolicy {
+    /// The group of a segment, `stream` is the playlist it is prepared for
+    pub fn get_group(&self, origin: &str, stream: Option<&str>) -> String {
+        if self.group_by == QuotaGroup::Stream
+            && let Some(stream) = stream
+        {
+            return stream.to_owned();
+        }
+
+        Url::parse(origin)
+            .ok()
+            .and_then(|x| x.host_str().map(str::to_owned))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::caching::{QuotaGroup, QuotaPolicy};
+
+    #[test]
+    fn test_group() {
+        let mut policy = QuotaPolicy::default();
+        let stream = Some("http://example.com/live.m3u8");
+        assert_eq!(
+            policy.get_group("http://cdn.example.com/0.ts", stream),
+            "http://example.com/live.m3u8"
+        );
+        assert_eq!(
+            policy.get_group("http://cdn.example.com/0.ts", None),
+            "cdn.example.com"
+        );
+
+        policy.group_by = QuotaGroup::Host;
+        assert_eq!(
+            policy.get_group("http://cdn.example.com/0.ts", stream),
+            "cdn.example.com"
+        );
+    }
+}
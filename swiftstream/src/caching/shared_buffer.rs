@@ -0,0 +1,376 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, stream};
+use tokio::sync::Notify;
+
+use crate::caching::{CacheResource, Freshness, MemoryBudget};
+
+/// A buffer filled by the downloader and read by any number of clients at the same time,
+/// readers wait for the bytes that are not arrived yet
+pub struct SharedBuffer {
+    state: Mutex<BufferState>,
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct BufferState {
+    chunks: Vec<Bytes>,
+    /// The offset of each chunk in the whole buffer
+    offsets: Vec<usize>,
+    len: usize,
+    /// Bytes reserved in each budget
+    reserved: usize,
+    budgets: Vec<Arc<MemoryBudget>>,
+    head: Option<BufferHead>,
+    freshness: Freshness,
+    status: BufferStatus,
+}
+
+/// Known once the origin server responded
+#[derive(Clone, Debug)]
+pub struct BufferHead {
+    pub content_type: String,
+    pub content_length: Option<u64>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum BufferStatus {
+    #[default]
+    Loading,
+    Finished,
+    /// With the HTTP status sent to clients
+    Failed(u16),
+}
+
+enum ReadResult {
+    Data(Bytes),
+    End,
+    Failed,
+}
+
+impl BufferState {
+    /// `None` if the bytes at `position` are not arrived yet
+    fn read_at(&self, position: usize, end: usize) -> Option<ReadResult> {
+        if position < self.len {
+            let index = self.offsets.partition_point(|x| *x <= position) - 1;
+            let offset = self.offsets[index];
+            let chunk = &self.chunks[index];
+            let chunk_end = chunk.len().min(end - offset);
+            return Some(ReadResult::Data(chunk.slice(position - offset..chunk_end)));
+        }
+
+        match self.status {
+            BufferStatus::Loading => None,
+            BufferStatus::Finished => Some(ReadResult::End),
+            BufferStatus::Failed(_) => Some(ReadResult::Failed),
+        }
+    }
+}
+
+impl Drop for SharedBuffer {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        self.release(&mut state);
+    }
+}
+
+impl Default for SharedBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SharedBuffer {
+    pub fn new() -> Self {
+        Self::with_budgets(Vec::new())
+    }
+
+    /// A buffer whose bytes are accounted in all the budgets, until it is failed or dropped
+    pub fn with_budgets(budgets: Vec<Arc<MemoryBudget>>) -> Self {
+        Self {
+            state: Mutex::new(BufferState {
+                budgets,
+                ..Default::default()
+            }),
+            notify: Notify::new(),
+        }
+    }
+
+    fn reserve(&self, state: &mut BufferState, size: usize) {
+        if size <= state.reserved {
+            return;
+        }
+
+        for budget in state.budgets.iter() {
+            budget.reserve(size - state.reserved);
+        }
+        state.reserved = size;
+    }
+
+    fn release(&self, state: &mut BufferState) {
+        for budget in state.budgets.iter() {
+            budget.release(state.reserved);
+        }
+        state.reserved = 0;
+    }
+
+    /// A finished buffer holding the whole resource
+    pub fn from_resource(resource: CacheResource) -> Self {
+        let buffer = Self::new();
+        buffer.set_head(resource.content_type, Some(resource.bytes.len() as u64));
+        buffer.push(resource.bytes);
+        buffer.finish();
+        buffer
+    }
+
+    fn update(&self, f: impl FnOnce(&mut BufferState)) {
+        f(&mut self.state.lock().unwrap());
+        self.notify.notify_waiters();
+    }
+
+    async fn wait_for<T>(&self, mut f: impl FnMut(&BufferState) -> Option<T>) -> T {
+        loop {
+            // register before checking, so a notification between them is not lost
+            let notified = self.notify.notified();
+            if let Some(result) = f(&self.state.lock().unwrap()) {
+                return result;
+            }
+            notified.await;
+        }
+    }
+
+    pub fn set_head(&self, content_type: String, content_length: Option<u64>) {
+        self.update(|state| {
+            self.reserve(state, content_length.unwrap_or_default() as usize);
+            state.head = Some(BufferHead {
+                content_type,
+                content_length,
+            })
+        });
+    }
+
+    pub fn push(&self, bytes: Bytes) {
+        if bytes.is_empty() {
+            return;
+        }
+
+        self.update(|state| {
+            state.offsets.push(state.len);
+            state.len += bytes.len();
+            state.chunks.push(bytes);
+            self.reserve(state, state.len);
+        });
+    }
+
+    pub fn finish(&self) {
+        self.update(|state| state.status = BufferStatus::Finished);
+    }
+
+    /// Mark as failed and release the bytes, readers will get an error,
+    /// and new clients will get the status
+    pub fn fail(&self, status: u16) {
+        self.update(|state| {
+            state.status = BufferStatus::Failed(status);
+            state.chunks.clear();
+            state.offsets.clear();
+            state.len = 0;
+            self.release(state);
+        });
+    }
+
+    /// Replace the content of a finished buffer with the same bytes held elsewhere,
+    /// and stop accounting it in `budget`, which the holder of the bytes is accounted in
+    pub fn share(&self, bytes: Bytes, budget: &Arc<MemoryBudget>) {
+        self.update(|state| {
+            if state.status != BufferStatus::Finished || bytes.len() != state.len {
+                return;
+            }
+
+            state.offsets = vec![0];
+            state.chunks = vec![bytes];
+            if let Some(index) = state.budgets.iter().position(|x| Arc::ptr_eq(x, budget)) {
+                state.budgets.remove(index).release(state.reserved);
+            }
+        });
+    }
+
+    /// Drop all bytes received, so it can be filled again, only if no one is reading
+    pub fn reset(&self) {
+        self.update(|state| {
+            state.status = BufferStatus::Loading;
+            state.head = None;
+            state.freshness = Freshness::default();
+            state.chunks.clear();
+            state.offsets.clear();
+            state.len = 0;
+            self.release(state);
+        });
+    }
+
+    /// Set before the head by the downloader
+    pub fn set_freshness(&self, freshness: Freshness) {
+        self.update(|state| state.freshness = freshness);
+    }
+
+    pub fn get_freshness(&self) -> Freshness {
+        self.state.lock().unwrap().freshness
+    }
+
+    pub fn get_head(&self) -> Option<BufferHead> {
+        self.state.lock().unwrap().head.clone()
+    }
+
+    /// The bytes received so far
+    pub fn get_chunks(&self) -> Vec<Bytes> {
+        self.state.lock().unwrap().chunks.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.lock().unwrap().status == BufferStatus::Finished
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self.state.lock().unwrap().status, BufferStatus::Failed(_))
+    }
+
+    /// Wait until the origin server responded, or the status if failed
+    pub async fn wait_head(&self) -> Result<BufferHead, u16> {
+        self.wait_for(|state| match state.status {
+            BufferStatus::Failed(status) => Some(Err(status)),
+            _ => state.head.clone().map(Ok),
+        })
+        .await
+    }
+
+    /// Wait until the total length is known, or the status if failed
+    pub async fn wait_length(&self) -> Result<u64, u16> {
+        self.wait_for(|state| match state.status {
+            BufferStatus::Failed(status) => Some(Err(status)),
+            BufferStatus::Finished => Some(Ok(state.len as u64)),
+            BufferStatus::Loading => state.head.as_ref().and_then(|x| x.content_length).map(Ok),
+        })
+        .await
+    }
+
+    /// Wait until finished, and collect the whole resource, `None` if failed
+    pub async fn wait_complete(&self) -> Option<CacheResource> {
+        self.wait_for(|state| match state.status {
+            BufferStatus::Loading => None,
+            BufferStatus::Failed(_) => Some(None),
+            BufferStatus::Finished => {
+                // a single chunk is shared as is, or they are joined once
+                let bytes = match state.chunks.as_slice() {
+                    [chunk] => chunk.clone(),
+                    chunks => {
+                        let mut bytes = BytesMut::with_capacity(state.len);
+                        for chunk in chunks {
+                            bytes.extend_from_slice(chunk);
+                        }
+                        bytes.freeze()
+                    }
+                };
+
+                Some(Some(CacheResource {
+                    bytes,
+                    content_type: state
+                        .head
+                        .as_ref()
+                        .map(|x| x.content_type.clone())
+                        .unwrap_or_default(),
+                }))
+            }
+        })
+        .await
+    }
+
+    /// Read `start..end` (or to the end if `end` is `None`), bytes are yielded as soon as they arrive
+    pub fn stream(
+        self: Arc<Self>,
+        start: usize,
+        end: Option<usize>,
+    ) -> impl Stream<Item = Result<Bytes, io::Error>> + Send + 'static {
+        let end = end.unwrap_or(usize::MAX);
+        stream::unfold((self, start), move |(buffer, position)| async move {
+            if position >= end {
+                return None;
+            }
+
+            match buffer.wait_for(|state| state.read_at(position, end)).await {
+                ReadResult::Data(bytes) => {
+                    let position = position + bytes.len();
+                    Some((Ok(bytes), (buffer, position)))
+                }
+                ReadResult::End => None,
+                ReadResult::Failed => Some((
+                    Err(io::Error::new(
+                        io::ErrorKind::Interrupted,
+                        "Failed to load data",
+                    )),
+                    // stop at the next poll
+                    (buffer, end),
+                )),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use futures::{StreamExt, executor::block_on, join};
+
+    use crate::caching::SharedBuffer;
+
+    #[test]
+    fn test_read_while_loading() {
+        let buffer = Arc::new(SharedBuffer::new());
+        buffer.set_head("video/mp2t".into(), Some(6));
+        buffer.push(Bytes::from_static(b"abc"));
+
+        let mut stream = Box::pin(buffer.clone().stream(1, None));
+        assert_eq!(block_on(stream.next()).unwrap().unwrap(), "bc");
+
+        // the reader waits for the writer
+        let (read, _) = block_on(async {
+            join!(stream.next(), async {
+                buffer.push(Bytes::from_static(b"def"));
+                buffer.finish();
+            })
+        });
+        assert_eq!(read.unwrap().unwrap(), "def");
+        assert!(block_on(stream.next()).is_none());
+
+        let ranged = buffer.clone().stream(2, Some(4));
+        let ranged = block_on(ranged.map(|x| x.unwrap()).collect::<Vec<_>>());
+        assert_eq!(ranged, vec!["c", "d"]);
+
+        let resource = block_on(buffer.wait_complete()).unwrap();
+        assert_eq!(resource.bytes.as_ref(), b"abcdef");
+    }
+
+    #[test]
+    fn test_failed() {
+        let buffer = Arc::new(SharedBuffer::new());
+        buffer.push(Bytes::from_static(b"abc"));
+        buffer.fail(404);
+
+        let mut stream = Box::pin(buffer.clone().stream(0, None));
+        assert!(block_on(stream.next()).unwrap().is_err());
+        assert!(block_on(stream.next()).is_none());
+        assert_eq!(block_on(buffer.wait_head()).unwrap_err(), 404);
+    }
+}
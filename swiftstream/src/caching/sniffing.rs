@@ -0,0 +1,242 @@
+use std::sync::Arc;
+
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use serde::Deserialize;
+use url::Url;
+
+use crate::caching::{SharedBuffer, matches_host};
+
+/// Find the real payload of segments disguised as other files
+/// (an image header prepended to TS, served as `.png`...), and strip what is before it
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SniffingConfig {
+    /// The hosts whose segments are sniffed, `*.example.com` for subdomains, or `*` for all
+    pub hosts: Vec<String>,
+    /// How many leading bytes may be stripped
+    pub max_skip: usize,
+}
+
+impl Default for SniffingConfig {
+    fn default() -> Self {
+        Self {
+            hosts: vec!["*".into()],
+            max_skip: 64 * 1024,
+        }
+    }
+}
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+/// Packets in a row that must start with the sync byte
+const TS_SYNC_PACKETS: usize = 8;
+
+/// Boxes a fMP4 segment (or its init segment) starts with
+const MP4_BOXES: [&[u8]; 4] = [b"ftyp", b"styp", b"moof", b"sidx"];
+
+impl SniffingConfig {
+    pub fn applies_to(&self, origin: &str) -> bool {
+        let host = Url::parse(origin)
+            .ok()
+            .and_then(|x| x.host_str().map(str::to_owned))
+            .unwrap_or_default();
+        self.hosts.iter().any(|x| matches_host(x, &host))
+    }
+
+    /// Bytes needed to decide
+    fn get_window(&self) -> usize {
+        self.max_skip + TS_PACKET_SIZE * TS_SYNC_PACKETS
+    }
+
+    /// Where the payload starts, and its Content-Type
+    pub fn find_payload(&self, bytes: &[u8]) -> Option<(usize, &'static str)> {
+        let last = self.max_skip.min(bytes.len().saturating_sub(1));
+        (0..=last).find_map(|position| {
+            if is_ts_at(bytes, position) {
+                Some((position, "video/mp2t"))
+            } else if is_mp4_at(bytes, position) {
+                Some((position, "video/mp4"))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Copy `source` into `target` as it arrives, with the payload found and the Content-Type corrected,
+    /// it is copied unchanged if no payload is found
+    pub async fn forward(&self, source: Arc<SharedBuffer>, target: &SharedBuffer) {
+        let Ok(head) = source.wait_head().await else {
+            return;
+        };
+        let freshness = source.get_freshness();
+        target.set_freshness(freshness);
+
+        let mut stream = Box::pin(source.stream(0, None).fuse());
+        let first = match stream.next().await {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(_)) => return,
+            None => Bytes::new(),
+        };
+
+        // media types, and bodies showing a payload at their start, are forwarded at once
+        let (content_type, offset, first) = if !is_disguise(&head.content_type) {
+            (head.content_type, 0, first)
+        } else if let Some(content_type) = get_start_payload(&first) {
+            (content_type.to_owned(), 0, first)
+        } else {
+            // hold the first bytes until they are enough to decide
+            let mut window = BytesMut::from(first);
+            while window.len() < self.get_window() {
+                match stream.next().await {
+                    Some(Ok(chunk)) => window.extend_from_slice(&chunk),
+                    Some(Err(_)) => return,
+                    None => break,
+                }
+            }
+
+            let window = window.freeze();
+            match self.find_payload(&window) {
+                Some((offset, content_type)) => {
+                    (content_type.to_owned(), offset, window.slice(offset..))
+                }
+                None => (head.content_type, 0, window),
+            }
+        };
+        target.set_head(
+            content_type,
+            head.content_length.map(|x| x.saturating_sub(offset as u64)),
+        );
+
+        target.push(first);
+        while let Some(Ok(chunk)) = stream.next().await {
+            target.push(chunk);
+        }
+    }
+}
+
+/// The Content-Type of the payload the chunk starts with, if it holds enough packets to tell
+fn get_start_payload(chunk: &[u8]) -> Option<&'static str> {
+    if chunk.len() >= TS_PACKET_SIZE * TS_SYNC_PACKETS && is_ts_at(chunk, 0) {
+        Some("video/mp2t")
+    } else if is_mp4_at(chunk, 0) {
+        Some("video/mp4")
+    } else {
+        None
+    }
+}
+
+/// Whether a segment served as this Content-Type may be a disguised one,
+/// anything but audio and video, which may be encrypted and look like noise
+fn is_disguise(content_type: &str) -> bool {
+    let content_type = content_type.trim().to_ascii_lowercase();
+    !content_type.starts_with("video/") && !content_type.starts_with("audio/")
+}
+
+fn is_ts_at(bytes: &[u8], position: usize) -> bool {
+    // enough packets in a row, or whole packets to the end of a short body
+    let rest = bytes.len() - position;
+    let packets = if rest >= TS_PACKET_SIZE * TS_SYNC_PACKETS {
+        TS_SYNC_PACKETS
+    } else if rest > 0 && rest.is_multiple_of(TS_PACKET_SIZE) {
+        rest / TS_PACKET_SIZE
+    } else {
+        return false;
+    };
+
+    (0..packets).all(|i| bytes[position + i * TS_PACKET_SIZE] == TS_SYNC_BYTE)
+}
+
+fn is_mp4_at(bytes: &[u8], position: usize) -> bool {
+    let Some(header) = bytes.get(position..position + 8) else {
+        return false;
+    };
+    let size = u32::from_be_bytes(header[0..4].try_into().unwrap());
+    size >= 8 && MP4_BOXES.contains(&&header[4..8])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use bytes::Bytes;
+    use futures::{StreamExt, executor::block_on, join};
+    use tokio::{runtime::Runtime, time::timeout};
+
+    use crate::caching::{SharedBuffer, SniffingConfig};
+
+    fn create_ts(packets: usize) -> Vec<u8> {
+        let mut packet = [0u8; 188];
+        packet[0] = 0x47;
+        packet.repeat(packets)
+    }
+
+    #[test]
+    fn test_find_payload() {
+        let config = SniffingConfig::default();
+
+        // a PNG header before the TS packets
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend(create_ts(4));
+        assert_eq!(config.find_payload(&png), Some((16, "video/mp2t")));
+
+        let mut mp4 = b"GIF89a".to_vec();
+        mp4.extend_from_slice(b"\0\0\0\x18styp");
+        assert_eq!(config.find_payload(&mp4), Some((6, "video/mp4")));
+
+        assert_eq!(config.find_payload(b"<html></html>"), None);
+    }
+
+    #[test]
+    fn test_forward() {
+        let config = SniffingConfig::default();
+        let source = Arc::new(SharedBuffer::new());
+        let target = SharedBuffer::new();
+
+        let mut payload = b"junk".to_vec();
+        payload.extend(create_ts(2));
+
+        let fill = async {
+            source.set_head("image/png".into(), Some(payload.len() as u64));
+            source.push(Bytes::copy_from_slice(&payload[..100]));
+            source.push(Bytes::copy_from_slice(&payload[100..]));
+            source.finish();
+        };
+        block_on(async { join!(config.forward(source.clone(), &target), fill) });
+
+        let head = block_on(target.wait_head()).unwrap();
+        assert_eq!(head.content_type, "video/mp2t");
+        assert_eq!(head.content_length, Some(376));
+        assert_eq!(target.len(), 376);
+    }
+
+    #[test]
+    fn test_forward_at_once() {
+        let config = SniffingConfig::default();
+        let runtime = Runtime::new().unwrap();
+
+        // not held for the window, the first chunk reaches the target before the rest arrives,
+        // and the disguise is still corrected
+        for (content_type, first) in [
+            ("video/mp2t", b"junk".to_vec()),
+            ("image/png", create_ts(8)),
+        ] {
+            let source = Arc::new(SharedBuffer::new());
+            let target = Arc::new(SharedBuffer::new());
+            let fill = async {
+                source.set_head(content_type.into(), None);
+                source.push(Bytes::from(first.clone()));
+                let mut stream = Box::pin(target.clone().stream(0, None));
+                let chunk = timeout(Duration::from_secs(5), stream.next()).await;
+                assert_eq!(chunk.unwrap().unwrap().unwrap(), first);
+                source.push(Bytes::from(create_ts(1)));
+                source.finish();
+            };
+            runtime.block_on(async { join!(config.forward(source.clone(), &target), fill) });
+
+            let head = runtime.block_on(target.wait_head()).unwrap();
+            assert_eq!(head.content_type, "video/mp2t");
+            assert_eq!(target.len(), first.len() + 188);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::caching::{CacheResource, ContentStore, MemoryBudget};
+
+/// Copies kept for a grace period after they expire,
+/// served instead of an error if the origin fails to give a new one
+pub struct StaleStore {
+    grace: Duration,
+    /// Accounts the bytes held, if any
+    budget: Option<Arc<MemoryBudget>>,
+    /// Accounts the copies instead, shared with the cached segments, if dedupe is enabled
+    content_store: Option<Arc<ContentStore>>,
+    entries: Mutex<HashMap<String, StaleEntry>>,
+}
+
+struct StaleEntry {
+    resource: CacheResource,
+    until: Instant,
+    /// Bytes reserved in the budget for it
+    reserved: usize,
+}
+
+impl StaleStore {
+    pub fn new(
+        grace: Duration,
+        budget: Option<Arc<MemoryBudget>>,
+        content_store: Option<Arc<ContentStore>>,
+    ) -> Self {
+        Self {
+            grace,
+            budget,
+            content_store,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.grace.is_zero()
+    }
+
+    /// Keep the copy for the grace period from now, replacing the older one
+    pub fn put(&self, key: String, mut resource: CacheResource) {
+        if !self.is_enabled() {
+            return;
+        }
+        if let Some(content_store) = &self.content_store {
+            resource.bytes = content_store.intern(resource.bytes);
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries.retain(|_, x| {
+            let keep = x.until > now;
+            if !keep {
+                self.release(x);
+            }
+            keep
+        });
+
+        let reserved = self.reserve(&resource);
+        let entry = StaleEntry {
+            resource,
+            until: now + self.grace,
+            reserved,
+        };
+        if let Some(entry) = entries.insert(key, entry) {
+            self.release(&entry);
+        }
+    }
+
+    /// The copy of `key`, if it is still in its grace period
+    pub fn get(&self, key: &str) -> Option<CacheResource> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        if entry.until > Instant::now() {
+            return Some(entry.resource.clone());
+        }
+
+        let entry = entries.remove(key).unwrap();
+        self.release(&entry);
+        None
+    }
+
+    pub fn remove(&self, key: &str) {
+        if let Some(entry) = self.entries.lock().unwrap().remove(key) {
+            self.release(&entry);
+        }
+    }
+
+    /// Drop the copies whose key matches
+    pub fn purge(&self, matches: impl Fn(&str) -> bool) {
+        self.entries.lock().unwrap().retain(|key, x| {
+            let keep = !matches(key);
+            if !keep {
+                self.release(x);
+            }
+            keep
+        });
+    }
+
+    /// Drop the copies closest to the end of their grace period until `fits` holds,
+    /// returns how many are dropped
+    pub fn shrink(&self, fits: impl Fn() -> bool) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let mut keys = entries
+            .iter()
+            .map(|(key, x)| (x.until, key.clone()))
+            .collect::<Vec<_>>();
+        keys.sort();
+
+        let mut dropped = 0;
+        for (_, key) in keys {
+            if fits() {
+                break;
+            }
+            let entry = entries.remove(&key).unwrap();
+            self.release(&entry);
+            dropped += 1;
+        }
+        dropped
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the bytes reserved, none if the content store accounts them
+    fn reserve(&self, resource: &CacheResource) -> usize {
+        match &self.budget {
+            Some(budget) if self.content_store.is_none() => {
+                budget.reserve(resource.bytes.len());
+                resource.bytes.len()
+            }
+            _ => 0,
+        }
+    }
+
+    fn release(&self, entry: &StaleEntry) {
+        if let Some(budget) = &self.budget {
+            budget.release(entry.reserved);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread::sleep, time::Duration};
+
+    use bytes::Bytes;
+
+    use crate::caching::{CacheResource, ContentStore, MemoryBudget, StaleStore};
+
+    fn create_resource(bytes: &'static [u8]) -> CacheResource {
+        CacheResource {
+            bytes: Bytes::from_static(bytes),
+            content_type: "video/mp2t".into(),
+        }
+    }
+
+    #[test]
+    fn test_grace() {
+        let budget = Arc::new(MemoryBudget::new(6));
+        let store = StaleStore::new(Duration::from_millis(50), Some(budget.clone()), None);
+
+        store.put("a".into(), create_resource(b"abc"));
+        store.put("b".into(), create_resource(b"defg"));
+        assert_eq!(budget.get_used(), 7);
+        assert_eq!(store.get("a").unwrap().bytes, "abc");
+
+        // the oldest goes first
+        assert_eq!(store.shrink(|| !budget.is_exceeded()), 1);
+        assert!(store.get("a").is_none());
+        assert_eq!(budget.get_used(), 4);
+
+        sleep(Duration::from_millis(60));
+        assert!(store.get("b").is_none());
+        assert_eq!(budget.get_used(), 0);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_shared() {
+        let budget = Arc::new(MemoryBudget::new(1024));
+        let content_store = Arc::new(ContentStore::new(budget.clone()));
+        let store = StaleStore::new(
+            Duration::from_secs(60),
+            Some(budget.clone()),
+            Some(content_store.clone()),
+        );
+
+        // accounted once, by the content store, shared with the cached segment
+        let cached = content_store.intern(Bytes::from(b"abc".to_vec()));
+        store.put("a".into(), create_resource(b"abc"));
+        store.put("b".into(), create_resource(b"abc"));
+        assert_eq!(budget.get_used(), 3);
+
+        drop(cached);
+        store.remove("a");
+        assert_eq!(budget.get_used(), 3);
+        store.remove("b");
+        assert_eq!(budget.get_used(), 0);
+    }
+}
@@ -1,17 +1,18 @@
 use std::{
     collections::HashMap,
-    io::Cursor,
     sync::Arc,
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use bytes::Bytes;
 use log::{debug, warn};
-use mediastream_rs::format::M3uPlaylist;
+use mediastream_rs::parse_str;
 use reqwest::Client;
+use serde::Serialize;
 use tokio::{sync::RwLock, time::sleep};
 use url::Url;
 
-use crate::{caching::CachePool, transfer::parse_m3u8_async};
+use crate::{Metrics, caching::CachePool};
 
 pub struct StreamTrackingPool {
     tracking: RwLock<HashMap<String, Arc<TrackingItem>>>,
@@ -19,6 +20,7 @@ pub struct StreamTrackingPool {
     interval: Duration,
     cache_pool: Arc<CachePool>,
     http_client: Client,
+    metrics: Arc<Metrics>,
 }
 
 impl StreamTrackingPool {
@@ -27,6 +29,7 @@ impl StreamTrackingPool {
         interval_secs: u16,
         cache_pool: Arc<CachePool>,
         http_client: Client,
+        metrics: Arc<Metrics>,
     ) -> Arc<Self> {
         Arc::new(Self {
             tracking: RwLock::new(HashMap::new()),
@@ -34,16 +37,22 @@ impl StreamTrackingPool {
             interval: Duration::from_secs(interval_secs.into()),
             cache_pool,
             http_client,
+            metrics,
         })
     }
 
-    async fn get_internal(self: &Arc<Self>, origin: String) -> Arc<TrackingItem> {
+    /// The item tracking `origin`, a new one is tracked until `expire`
+    async fn get_internal(
+        self: &Arc<Self>,
+        origin: String,
+        expire: SystemTime,
+    ) -> Arc<TrackingItem> {
         if let Some(item_ref) = self.tracking.read().await.get(&origin) {
             return item_ref.clone();
         }
 
         // new cache item
-        let result = Arc::new(TrackingItem::new(origin.clone()));
+        let result = Arc::new(TrackingItem::new(origin.clone(), expire));
         self.tracking.write().await.insert(origin, result.clone());
 
         // worker startup
@@ -56,37 +65,113 @@ impl StreamTrackingPool {
         result
     }
 
-    pub async fn drop(self: &Arc<Self>, origin: impl AsRef<str>) {
-        self.tracking.write().await.remove(origin.as_ref());
+    /// Count of the streams being tracked
+    pub async fn get_count(&self) -> usize {
+        self.tracking.read().await.len()
+    }
+
+    async fn drop(self: &Arc<Self>, item: &TrackingItem) {
+        let mut tracking = self.tracking.write().await;
+
+        // it may have been stopped, and the origin is tracked again by another item
+        if tracking
+            .get(&item.origin)
+            .is_some_and(|x| std::ptr::eq(x.as_ref(), item))
+        {
+            tracking.remove(&item.origin);
+        }
     }
 
     pub async fn track(self: &Arc<Self>, origin: impl AsRef<str>) {
-        let item = self.get_internal(origin.as_ref().to_owned()).await;
-        item.set_expire(SystemTime::now() + Duration::from_secs(self.time_limit_secs.into()))
+        self.track_for(origin, Duration::from_secs(self.time_limit_secs.into()))
             .await;
     }
 
-    async fn cache_prepare(&self, origin: impl AsRef<str>) {
-        self.cache_pool.prepare(origin).await;
+    /// Keep tracking the stream for at least the given duration from now,
+    /// a longer tracking already requested is kept
+    pub async fn track_for(self: &Arc<Self>, origin: impl AsRef<str>, duration: Duration) {
+        let expire = SystemTime::now() + duration;
+        let item = self.get_internal(origin.as_ref().to_owned(), expire).await;
+        item.extend_expire(expire).await;
+    }
+
+    /// Stop tracking the stream, returns `false` if it isn't tracked
+    pub async fn untrack(self: &Arc<Self>, origin: impl AsRef<str>) -> bool {
+        let removed = self.tracking.write().await.remove(origin.as_ref());
+        match removed {
+            Some(item) => {
+                // the worker will quit on its next round
+                item.set_expire(UNIX_EPOCH).await;
+                true
+            }
+            None => false,
+        }
     }
+
+    /// Describe all streams being tracked
+    pub async fn list(&self) -> Vec<TrackingItemInfo> {
+        let items = self
+            .tracking
+            .read()
+            .await
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut result = Vec::with_capacity(items.len());
+        for item in items {
+            result.push(TrackingItemInfo {
+                origin: item.origin.clone(),
+                expire: unix_secs(*item.expire.read().await),
+                last_refresh: item.last_refresh.read().await.map(unix_secs),
+                last_error: item.last_error.read().await.clone(),
+            });
+        }
+
+        result
+    }
+
+    async fn cache_prepare(&self, origin: impl AsRef<str>, stream: &str) {
+        self.cache_pool.prepare(origin, Some(stream)).await;
+    }
+}
+
+/// A snapshot of a stream being tracked, times are Unix timestamps in seconds
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackingItemInfo {
+    pub origin: String,
+    pub expire: u64,
+    pub last_refresh: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
 }
 
 struct TrackingItem {
     origin: String,
     expire: RwLock<SystemTime>,
+    last_refresh: RwLock<Option<SystemTime>>,
+    last_error: RwLock<Option<String>>,
 }
 
 impl TrackingItem {
-    pub fn new(origin: String) -> Self {
+    pub fn new(origin: String, expire: SystemTime) -> Self {
         Self {
             origin,
-            expire: RwLock::new(SystemTime::now() + Duration::from_secs(30)),
+            expire: RwLock::new(expire),
+            last_refresh: RwLock::new(None),
+            last_error: RwLock::new(None),
         }
     }
 
     pub async fn manage_worker(&self, tracking_pool: Arc<StreamTrackingPool>) {
         loop {
-            let expire = self.expire.read().await.clone();
+            let expire = *self.expire.read().await;
             let now = SystemTime::now();
             if expire < now {
                 // expired
@@ -94,8 +179,15 @@ impl TrackingItem {
             }
 
             // keep track
-            if let Err(e) = self.keep_track(&tracking_pool).await {
-                warn!("Error while keep track of {}: {}", self.origin, e);
+            match self.keep_track(&tracking_pool).await {
+                Err(e) => {
+                    warn!("Error while keep track of {}: {}", self.origin, e);
+                    *self.last_error.write().await = Some(e.to_string());
+                }
+                Ok(()) => {
+                    *self.last_refresh.write().await = Some(SystemTime::now());
+                    *self.last_error.write().await = None;
+                }
             }
             debug!("Kept track of {}", self.origin);
 
@@ -107,36 +199,36 @@ impl TrackingItem {
         }
 
         // expired, drop my self
-        tracking_pool.drop(&self.origin).await;
+        tracking_pool.drop(self).await;
     }
 
-    async fn prepare_all(
-        &self,
-        tracking_pool: &Arc<StreamTrackingPool>,
-        origin: impl AsRef<str>,
-        playlist: M3uPlaylist,
-    ) -> Result<(), anyhow::Error> {
-        let base_url = Url::parse(origin.as_ref())?;
-
-        // prepare all
-        for media in playlist.medias.iter() {
-            let media_location = media.location.clone();
-            let mut location = Url::parse(&media_location);
-            if location == Err(url::ParseError::RelativeUrlWithoutBase) {
-                location = base_url.join(&media_location);
-            }
-            let location = location?.to_string();
-
-            tracking_pool.cache_prepare(location).await;
-        }
-
-        Ok(())
+    /// The segment locations of a playlist, resolved against its origin, parsed off
+    /// the runtime as playlists may be large
+    async fn get_locations(origin: &str, data: Bytes) -> Result<Vec<String>, anyhow::Error> {
+        let base_url = Url::parse(origin)?;
+        tokio::task::spawn_blocking(move || {
+            // borrowing from the response body
+            let playlist = parse_str(str::from_utf8(&data)?)?;
+            playlist
+                .medias
+                .iter()
+                .map(|media| {
+                    let mut location = Url::parse(media.location);
+                    if location == Err(url::ParseError::RelativeUrlWithoutBase) {
+                        location = base_url.join(media.location);
+                    }
+                    Ok(location?.to_string())
+                })
+                .collect()
+        })
+        .await?
     }
 
     async fn keep_track(
         &self,
         tracking_pool: &Arc<StreamTrackingPool>,
     ) -> Result<(), anyhow::Error> {
+        let start = Instant::now();
         let data = tracking_pool
             .http_client
             .get(&self.origin)
@@ -145,11 +237,17 @@ impl TrackingItem {
             .bytes()
             .await?;
 
-        // parse
-        let playlist = parse_m3u8_async(Cursor::new(data)).await?;
+        let locations = Self::get_locations(&self.origin, data).await?;
+        tracking_pool
+            .metrics
+            .playlist_refresh_duration
+            .with_label_values(&["tracking"])
+            .observe(start.elapsed().as_secs_f64());
 
-        self.prepare_all(tracking_pool, &self.origin, playlist)
-            .await?;
+        // prepare all
+        for location in locations {
+            tracking_pool.cache_prepare(location, &self.origin).await;
+        }
 
         Ok(())
     }
@@ -158,4 +256,10 @@ impl TrackingItem {
         let mut expire_ref = self.expire.write().await;
         *expire_ref = expire;
     }
+
+    /// Move the expiry to `expire` if it is later
+    pub async fn extend_expire(&self, expire: SystemTime) {
+        let mut expire_ref = self.expire.write().await;
+        *expire_ref = (*expire_ref).max(expire);
+    }
 }
@@ -0,0 +1,307 @@
+use bytes::Bytes;
+use serde::Deserialize;
+
+/// A downloaded segment, as the chunks it arrived in
+pub struct SegmentView<'a> {
+    pub content_type: &'a str,
+    pub content_length: Option<u64>,
+    pub chunks: &'a [Bytes],
+}
+
+impl SegmentView<'_> {
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|x| x.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copy up to `size` bytes from `position`
+    pub fn read(&self, position: usize, size: usize) -> Vec<u8> {
+        let mut result = Vec::with_capacity(size);
+        let mut offset = 0;
+        for chunk in self.chunks.iter() {
+            let chunk_end = offset + chunk.len();
+            if chunk_end > position {
+                let start = position.max(offset) - offset;
+                let end = chunk.len().min(position + size - offset);
+                result.extend_from_slice(&chunk[start..end]);
+                if result.len() == size {
+                    break;
+                }
+            }
+            offset = chunk_end;
+        }
+        result
+    }
+}
+
+/// Checks a downloaded segment before it is finished and cached,
+/// returns why it is rejected
+pub trait SegmentValidator: Send + Sync {
+    fn validate(&self, segment: &SegmentView) -> Result<(), String>;
+}
+
+/// Which of the built-in validators are enabled,
+/// they run once the download completes, by then the players reading the segment
+/// while it downloads have received its bytes and only see the download fail
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ValidationConfig {
+    /// The body must be as long as the Content-Length
+    pub content_length: bool,
+    /// The body must be at least this long (in bytes)
+    pub min_size: usize,
+    /// MPEG-TS segments must have the sync byte at the start of every packet,
+    /// segments encrypted as a whole (`#EXT-X-KEY` with AES-128) have none
+    pub ts_sync: bool,
+    /// fMP4 segments must be made of well-formed boxes
+    pub mp4_boxes: bool,
+    /// Reject HTML bodies, usually error pages served with 200
+    pub reject_markup: bool,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            content_length: true,
+            min_size: 1,
+            ts_sync: false,
+            mp4_boxes: true,
+            reject_markup: true,
+        }
+    }
+}
+
+impl ValidationConfig {
+    pub fn create(&self) -> Vec<Box<dyn SegmentValidator>> {
+        let mut validators: Vec<Box<dyn SegmentValidator>> = Vec::new();
+        if self.content_length {
+            validators.push(Box::new(ContentLengthValidator));
+        }
+        if self.min_size > 0 {
+            validators.push(Box::new(MinSizeValidator(self.min_size)));
+        }
+        if self.reject_markup {
+            validators.push(Box::new(MarkupValidator));
+        }
+        if self.ts_sync {
+            validators.push(Box::new(TsSyncValidator));
+        }
+        if self.mp4_boxes {
+            validators.push(Box::new(Mp4BoxValidator));
+        }
+        validators
+    }
+}
+
+pub struct ContentLengthValidator;
+
+impl SegmentValidator for ContentLengthValidator {
+    fn validate(&self, segment: &SegmentView) -> Result<(), String> {
+        match segment.content_length {
+            Some(length) if length != segment.len() as u64 => {
+                Err(format!("received {} bytes of {}", segment.len(), length))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct MinSizeValidator(pub usize);
+
+impl SegmentValidator for MinSizeValidator {
+    fn validate(&self, segment: &SegmentView) -> Result<(), String> {
+        if segment.len() < self.0 {
+            return Err(format!("only {} bytes", segment.len()));
+        }
+        Ok(())
+    }
+}
+
+pub struct MarkupValidator;
+
+/// Subtitle segments may be XML (TTML) or JSON, and some origins serve real segments
+/// as `text/html`, so only HTML bodies are rejected
+const MARKUP_PREFIXES: [&[u8]; 2] = [b"<!doctype", b"<html"];
+
+impl SegmentValidator for MarkupValidator {
+    fn validate(&self, segment: &SegmentView) -> Result<(), String> {
+        let mut prefix = segment.read(0, 64);
+        prefix.retain(|x| !x.is_ascii_whitespace());
+        prefix.make_ascii_lowercase();
+        if MARKUP_PREFIXES.iter().any(|x| prefix.starts_with(x)) {
+            return Err("the body looks like a document".into());
+        }
+        Ok(())
+    }
+}
+
+pub struct TsSyncValidator;
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+
+impl SegmentValidator for TsSyncValidator {
+    fn validate(&self, segment: &SegmentView) -> Result<(), String> {
+        let is_ts = segment.content_type.contains("mp2t")
+            || segment.chunks.first().and_then(|x| x.first()) == Some(&TS_SYNC_BYTE);
+        if !is_ts {
+            return Ok(());
+        }
+
+        let mut offset = 0;
+        for chunk in segment.chunks.iter() {
+            // the first packet start in this chunk
+            let mut position = (TS_PACKET_SIZE - offset % TS_PACKET_SIZE) % TS_PACKET_SIZE;
+            while position < chunk.len() {
+                if chunk[position] != TS_SYNC_BYTE {
+                    return Err(format!("no sync byte at {}", offset + position));
+                }
+                position += TS_PACKET_SIZE;
+            }
+            offset += chunk.len();
+        }
+
+        if offset % TS_PACKET_SIZE != 0 {
+            return Err(format!("{} bytes is not whole packets", offset));
+        }
+        Ok(())
+    }
+}
+
+pub struct Mp4BoxValidator;
+
+/// Top-level boxes a fragmented MP4 segment may start with
+const MP4_FIRST_BOXES: [&[u8]; 7] = [
+    b"ftyp", b"styp", b"sidx", b"moof", b"emsg", b"prft", b"free",
+];
+
+impl SegmentValidator for Mp4BoxValidator {
+    fn validate(&self, segment: &SegmentView) -> Result<(), String> {
+        let first = segment.read(4, 4);
+        if !MP4_FIRST_BOXES.contains(&first.as_slice()) {
+            return Ok(());
+        }
+
+        // the boxes must cover the body exactly
+        let len = segment.len() as u64;
+        let mut position = 0;
+        while position < len {
+            let header = segment.read(position as usize, 16);
+            if header.len() < 8 {
+                return Err(format!("truncated box header at {}", position));
+            }
+
+            let box_type = &header[4..8];
+            if !box_type.iter().all(|x| x.is_ascii_graphic() || *x == b' ') {
+                return Err(format!("invalid box type at {}", position));
+            }
+
+            let size = match u32::from_be_bytes(header[0..4].try_into().unwrap()) {
+                // to the end
+                0 => len - position,
+                1 if header.len() == 16 => u64::from_be_bytes(header[8..16].try_into().unwrap()),
+                1 => return Err(format!("truncated box header at {}", position)),
+                size => size as u64,
+            };
+            if size < 8 || position + size > len {
+                return Err(format!("box at {} overruns the body", position));
+            }
+            position += size;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::caching::{SegmentView, ValidationConfig};
+
+    fn validate(content_type: &str, chunks: &[Bytes]) -> Result<(), String> {
+        validate_with(&ValidationConfig::default(), content_type, chunks)
+    }
+
+    fn validate_with(
+        config: &ValidationConfig,
+        content_type: &str,
+        chunks: &[Bytes],
+    ) -> Result<(), String> {
+        let segment = SegmentView {
+            content_type,
+            content_length: None,
+            chunks,
+        };
+        config
+            .create()
+            .iter()
+            .try_for_each(|x| x.validate(&segment))
+    }
+
+    #[test]
+    fn test_ts() {
+        let config = ValidationConfig {
+            ts_sync: true,
+            ..Default::default()
+        };
+        let validate = |content_type, chunks| validate_with(&config, content_type, chunks);
+
+        let mut packet = vec![0u8; 188];
+        packet[0] = 0x47;
+        let ts = [packet.clone(), packet.clone()].concat();
+
+        // packets split at any position
+        let chunks = [
+            Bytes::from(ts[..100].to_vec()),
+            Bytes::from(ts[100..].to_vec()),
+        ];
+        assert!(validate("video/mp2t", &chunks).is_ok());
+
+        let truncated = [Bytes::from(ts[..300].to_vec())];
+        assert!(validate("video/mp2t", &truncated).is_err());
+
+        let html = [Bytes::from_static(b"\n<!DOCTYPE html><html></html>")];
+        assert!(validate("video/mp2t", &html).is_err());
+        assert!(validate("text/html", &chunks).is_ok());
+        assert!(validate("video/mp2t", &[]).is_err());
+    }
+
+    #[test]
+    fn test_encrypted() {
+        // AES-128 segments look like noise, with no sync byte and any length
+        let encrypted = (0..1000u32)
+            .map(|x| (x.wrapping_mul(2654435761) >> 13) as u8)
+            .collect::<Vec<_>>();
+        assert!(validate("video/mp2t", &[Bytes::from(encrypted)]).is_ok());
+    }
+
+    #[test]
+    fn test_markup() {
+        let html = [Bytes::from_static(b"<html><body>Not Found</body></html>")];
+        assert!(validate("text/html; charset=utf-8", &html).is_err());
+        assert!(validate("application/octet-stream", &html).is_err());
+
+        // subtitles
+        let ttml = [Bytes::from_static(b"<?xml version=\"1.0\"?><tt></tt>")];
+        assert!(validate("application/ttml+xml", &ttml).is_ok());
+        let json = [Bytes::from_static(b"{\"cues\": []}")];
+        assert!(validate("application/json", &json).is_ok());
+    }
+
+    #[test]
+    fn test_mp4() {
+        let mut mp4 = Vec::new();
+        mp4.extend_from_slice(&[0, 0, 0, 16]);
+        mp4.extend_from_slice(b"styp");
+        mp4.extend_from_slice(&[0; 8]);
+        mp4.extend_from_slice(&[0, 0, 0, 8]);
+        mp4.extend_from_slice(b"mdat");
+        assert!(validate("video/mp4", &[Bytes::from(mp4.clone())]).is_ok());
+
+        mp4.truncate(20);
+        assert!(validate("video/mp4", &[Bytes::from(mp4)]).is_err());
+    }
+}
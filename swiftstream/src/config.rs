@@ -3,6 +3,11 @@ use std::{collections::HashMap, fs::File, path::Path};
 use anyhow::Result;
 use serde::Deserialize;
 
+use crate::caching::{
+    CacheStoreKind, EvictionPolicy, FailurePolicy, KeyRules, PinningConfig, QuotaPolicy,
+    SniffingConfig, TtlPolicy, ValidationConfig,
+};
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Config {
@@ -13,9 +18,49 @@ pub struct Config {
     pub track_expire: Option<u16>,
     pub track_interval: Option<u16>,
     pub download_threads: Option<u8>,
+    #[serde(default)]
+    pub cache_store: CacheStoreKind,
+    #[serde(default)]
+    pub eviction_policy: EvictionPolicy,
+    #[serde(default)]
+    pub redirect_when_full: bool,
+    #[serde(default)]
+    pub failure: FailurePolicy,
+    pub quota: Option<QuotaPolicy>,
+    #[serde(default)]
+    pub cache_key: KeyRules,
+    #[serde(default)]
+    pub dedupe: bool,
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    pub sniffing: Option<SniffingConfig>,
+    #[serde(default)]
+    pub ttl: TtlPolicy,
 
     #[serde(default)]
     pub http: HttpConfig,
+    pub disk_cache: Option<DiskCacheConfig>,
+    pub admin: Option<AdminConfig>,
+    pub pinning: Option<PinningConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminConfig {
+    #[serde(default = "default_admin_prefix")]
+    pub prefix: String,
+    pub token: String,
+}
+
+fn default_admin_prefix() -> String {
+    "/admin".into()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiskCacheConfig {
+    pub path: String,
+    pub size_limit: Option<u64>,
 }
 
 #[derive(Debug, Deserialize, Default)]
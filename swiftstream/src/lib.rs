@@ -1,7 +1,9 @@
 mod app_state;
 mod config;
+mod metrics;
 pub use app_state::*;
 pub use config::*;
+pub use metrics::*;
 pub mod caching;
 pub mod errors;
 pub mod routes;
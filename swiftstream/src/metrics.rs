@@ -0,0 +1,164 @@
+use std::sync::Arc;
+
+use axum::body::Body;
+use futures::StreamExt;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder, exponential_buckets,
+};
+
+/// Series exposed at `/metrics`
+pub struct Metrics {
+    registry: Registry,
+
+    /// Requests served from the cache, by tier (`memory` or `disk`)
+    pub cache_hits: IntCounterVec,
+    /// Requests that started a new download
+    pub cache_misses: IntCounter,
+    /// Requests that could not be cached because the pool is full
+    pub cache_rejects: IntCounter,
+    pub cache_bytes: IntGauge,
+    pub cache_items: IntGauge,
+    /// Bytes not held thanks to segments sharing identical payloads
+    pub dedupe_saved_bytes: IntGauge,
+    pub evictions: IntCounter,
+
+    pub download_duration: Histogram,
+    /// Failed downloads, by the kind of `DownloadError`
+    pub download_errors: IntCounterVec,
+    /// Stale copies served as the origin failed, by kind (`segment` or `playlist`)
+    pub stale_served: IntCounterVec,
+    /// Bytes downloaded from origin servers, by host
+    pub fetched_bytes: IntCounterVec,
+
+    pub tracked_streams: IntGauge,
+    /// Time to fetch and parse a playlist, by source (`tracking` or `media`)
+    pub playlist_refresh_duration: HistogramVec,
+
+    /// Bytes sent to clients, by route
+    pub served_bytes: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        let registry = Registry::new_custom(Some("swiftstream".into()), None).unwrap();
+        // 10ms to ~40s
+        let duration_buckets = exponential_buckets(0.01, 2.0, 13).unwrap();
+
+        let metrics = Self {
+            cache_hits: IntCounterVec::new(
+                Opts::new("cache_hits_total", "Requests served from the cache"),
+                &["tier"],
+            )
+            .unwrap(),
+            cache_misses: IntCounter::new(
+                "cache_misses_total",
+                "Requests that started a new download",
+            )
+            .unwrap(),
+            cache_rejects: IntCounter::new(
+                "cache_rejects_total",
+                "Requests not cached because the pool is full",
+            )
+            .unwrap(),
+            cache_bytes: IntGauge::new("cache_bytes", "Bytes held by the cache pool").unwrap(),
+            cache_items: IntGauge::new("cache_items", "Items held by the cache pool").unwrap(),
+            dedupe_saved_bytes: IntGauge::new(
+                "cache_dedupe_saved_bytes",
+                "Bytes saved by sharing identical payloads",
+            )
+            .unwrap(),
+            evictions: IntCounter::new("cache_evictions_total", "Items evicted from the pool")
+                .unwrap(),
+            download_duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "download_duration_seconds",
+                    "Time to download a segment from the origin",
+                )
+                .buckets(duration_buckets.clone()),
+            )
+            .unwrap(),
+            download_errors: IntCounterVec::new(
+                Opts::new("download_errors_total", "Failed downloads"),
+                &["kind"],
+            )
+            .unwrap(),
+            stale_served: IntCounterVec::new(
+                Opts::new(
+                    "stale_served_total",
+                    "Stale copies served as the origin failed",
+                ),
+                &["kind"],
+            )
+            .unwrap(),
+            fetched_bytes: IntCounterVec::new(
+                Opts::new(
+                    "fetched_bytes_total",
+                    "Bytes downloaded from origin servers",
+                ),
+                &["host"],
+            )
+            .unwrap(),
+            tracked_streams: IntGauge::new("tracked_streams", "Streams being tracked").unwrap(),
+            playlist_refresh_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "playlist_refresh_duration_seconds",
+                    "Time to fetch and parse a playlist",
+                )
+                .buckets(duration_buckets),
+                &["source"],
+            )
+            .unwrap(),
+            served_bytes: IntCounterVec::new(
+                Opts::new("served_bytes_total", "Bytes sent to clients"),
+                &["route"],
+            )
+            .unwrap(),
+            registry,
+        };
+
+        metrics.register_all();
+        Arc::new(metrics)
+    }
+
+    fn register_all(&self) {
+        let collectors: [Box<dyn prometheus::core::Collector>; 14] = [
+            Box::new(self.cache_hits.clone()),
+            Box::new(self.cache_misses.clone()),
+            Box::new(self.cache_rejects.clone()),
+            Box::new(self.cache_bytes.clone()),
+            Box::new(self.cache_items.clone()),
+            Box::new(self.dedupe_saved_bytes.clone()),
+            Box::new(self.evictions.clone()),
+            Box::new(self.download_duration.clone()),
+            Box::new(self.download_errors.clone()),
+            Box::new(self.stale_served.clone()),
+            Box::new(self.fetched_bytes.clone()),
+            Box::new(self.tracked_streams.clone()),
+            Box::new(self.playlist_refresh_duration.clone()),
+            Box::new(self.served_bytes.clone()),
+        ];
+        for collector in collectors {
+            self.registry.register(collector).unwrap();
+        }
+    }
+
+    /// Wrap a response body, counting the bytes sent to clients
+    pub fn count_body(&self, route: &str, body: Body) -> Body {
+        let counter = self.served_bytes.with_label_values(&[route]);
+        Body::from_stream(body.into_data_stream().inspect(move |x| {
+            if let Ok(bytes) = x {
+                counter.inc_by(bytes.len() as u64);
+            }
+        }))
+    }
+
+    /// Encode all series in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
@@ -0,0 +1,251 @@
+use std::time::Duration;
+
+use axum::{
+    Json, Router,
+    extract::{Query, Request, State},
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::Response,
+    routing::get,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AppStateRef,
+    caching::{CacheItemInfo, ContentStats, TrackingItemInfo},
+};
+
+#[derive(Deserialize)]
+pub struct PurgeQuery {
+    /// Purge the exact origin
+    pub origin: Option<String>,
+    /// Purge every origin starting with it, normalized like the cache keys
+    pub prefix: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TrackQuery {
+    pub origin: String,
+    /// How long to keep tracking (in seconds), defaults to `trackExpire`
+    pub duration: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct CountResponse {
+    pub count: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStatsResponse {
+    pub items: usize,
+    /// Bytes reserved in memory
+    pub bytes: usize,
+    /// `None` if dedupe is disabled
+    pub dedupe: Option<ContentStats>,
+}
+
+pub fn get_routes(app_state: &AppStateRef) -> Router<AppStateRef> {
+    Router::new()
+        .route("/cache", get(get_cache).delete(delete_cache))
+        .route("/cache/stats", get(get_cache_stats))
+        .route(
+            "/tracking",
+            get(get_tracking)
+                .post(post_tracking)
+                .delete(delete_tracking),
+        )
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), authorize))
+}
+
+/// Require `Authorization: Bearer {token}`
+async fn authorize(
+    State(state): State<AppStateRef>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let token = state
+        .config
+        .admin
+        .as_ref()
+        .map(|x| x.token.as_str())
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "))
+        .is_some_and(|x| constant_time_eq(x.as_bytes(), token.as_bytes()));
+    if !authorized {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Takes as long wherever the bytes differ, so the token can't be guessed byte by byte
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub async fn get_cache(State(state): State<AppStateRef>) -> Json<Vec<CacheItemInfo>> {
+    Json(state.cache_pool.list().await)
+}
+
+pub async fn get_cache_stats(State(state): State<AppStateRef>) -> Json<CacheStatsResponse> {
+    let (items, bytes) = state.cache_pool.get_stats().await;
+    Json(CacheStatsResponse {
+        items,
+        bytes,
+        dedupe: state.cache_pool.get_content_stats(),
+    })
+}
+
+pub async fn delete_cache(
+    State(state): State<AppStateRef>,
+    Query(query): Query<PurgeQuery>,
+) -> Result<Json<CountResponse>, StatusCode> {
+    let count = match (query.origin, query.prefix) {
+        (Some(origin), None) => {
+            let key = state.cache_pool.get_key(&origin);
+            state.cache_pool.purge(|x| x == key).await
+        }
+        (None, Some(prefix)) => {
+            let prefix = state.cache_pool.get_key(&prefix);
+            state.cache_pool.purge(|x| x.starts_with(&prefix)).await
+        }
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    Ok(Json(CountResponse { count }))
+}
+
+pub async fn get_tracking(State(state): State<AppStateRef>) -> Json<Vec<TrackingItemInfo>> {
+    Json(state.tracking_pool.list().await)
+}
+
+pub async fn post_tracking(
+    State(state): State<AppStateRef>,
+    Query(query): Query<TrackQuery>,
+) -> StatusCode {
+    match query.duration {
+        Some(duration) => {
+            state
+                .tracking_pool
+                .track_for(&query.origin, Duration::from_secs(duration))
+                .await
+        }
+        None => state.tracking_pool.track(&query.origin).await,
+    }
+
+    StatusCode::NO_CONTENT
+}
+
+pub async fn delete_tracking(
+    State(state): State<AppStateRef>,
+    Query(query): Query<TrackQuery>,
+) -> StatusCode {
+    if state.tracking_pool.untrack(&query.origin).await {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use axum::{Router, http::StatusCode, routing::get};
+    use reqwest::{Client, RequestBuilder};
+    use serde_yaml::Value;
+    use tokio::runtime::Runtime;
+
+    use crate::routes::{admin::constant_time_eq, serve_app, serve_local};
+
+    async fn send(request: RequestBuilder) -> (StatusCode, Value) {
+        let response = request.bearer_auth("t").send().await.unwrap();
+        let status = response.status();
+        let body = response.bytes().await.unwrap();
+        (status, serde_yaml::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[test]
+    fn test_token() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"token2"));
+    }
+
+    #[test]
+    fn test_admin() {
+        Runtime::new().unwrap().block_on(async {
+            let origin =
+                serve_local(Router::new().route("/{*path}", get(|| async { "segment" }))).await;
+            let (_, base_url) = serve_app(
+                "listenAddr: 127.0.0.1:0\nadmin:\n  token: t\ncacheKey:\n  ignoreCase: true",
+            )
+            .await;
+            let admin_url = format!("{}/admin", base_url);
+            let client = Client::new();
+
+            // rejected without the token, or with another one
+            let response = client
+                .get(format!("{}/cache", admin_url))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            let response = client
+                .get(format!("{}/cache", admin_url))
+                .bearer_auth("x")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+            for path in ["a/0.ts", "a/1.ts", "b/0.ts"] {
+                let origin = format!("{}/{}", origin, path);
+                let url = format!(
+                    "{}/stream?origin={}",
+                    base_url,
+                    urlencoding::encode(&origin)
+                );
+                let (status, _) = send(client.get(url)).await;
+                assert_eq!(status, StatusCode::OK);
+            }
+
+            // matched as the keys are, lowercased
+            let prefix = urlencoding::encode(&format!("{}/A/", origin)).into_owned();
+            let (status, body) =
+                send(client.delete(format!("{}/cache?prefix={}", admin_url, prefix))).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["count"], 2);
+            let (_, body) = send(client.get(format!("{}/cache", admin_url))).await;
+            assert_eq!(body.as_sequence().unwrap().len(), 1);
+            assert_eq!(body[0]["origin"], format!("{}/b/0.ts", origin).as_str());
+
+            // a shorter tracking doesn't cut a longer one
+            let playlist = urlencoding::encode(&format!("{}/live.m3u8", origin)).into_owned();
+            let tracking_url = format!("{}/tracking?origin={}", admin_url, playlist);
+            let (status, _) = send(client.post(format!("{}&duration=600", tracking_url))).await;
+            assert_eq!(status, StatusCode::NO_CONTENT);
+            let (status, _) = send(client.post(format!("{}&duration=5", tracking_url))).await;
+            assert_eq!(status, StatusCode::NO_CONTENT);
+
+            let (_, body) = send(client.get(format!("{}/tracking", admin_url))).await;
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            assert_eq!(body.as_sequence().unwrap().len(), 1);
+            assert!(body[0]["expire"].as_u64().unwrap() >= now + 590);
+
+            let (status, _) = send(client.delete(&tracking_url)).await;
+            assert_eq!(status, StatusCode::NO_CONTENT);
+            let (status, _) = send(client.delete(&tracking_url)).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+        });
+    }
+}
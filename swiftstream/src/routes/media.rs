@@ -1,15 +1,21 @@
-use std::io::Cursor;
+use std::{io::Cursor, time::Instant};
 
 use axum::{
     extract::{Query, State},
-    http::StatusCode,
+    http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
+use log::warn;
 use mediastream_rs::format::M3uPlaylist;
 use serde::Deserialize;
 use url::Url;
 
-use crate::{AppStateRef, internal_error_with_log, transfer::parse_m3u8_async};
+use crate::{
+    AppStateRef,
+    caching::{CacheResource, DownloadError},
+    internal_error_with_log,
+    transfer::parse_m3u8_async,
+};
 
 #[derive(Deserialize)]
 pub struct MediaQuery {
@@ -22,11 +28,7 @@ async fn prepare_all(
     origin: impl AsRef<str>,
 ) -> Result<(), anyhow::Error> {
     let origin_base_url = Url::parse(origin.as_ref())?;
-    let base_url = state
-        .config
-        .base_url
-        .clone()
-        .unwrap_or_else(|| String::new());
+    let base_url = state.config.base_url.clone().unwrap_or_default();
 
     // prepare all
     for media in playlist.medias.iter_mut() {
@@ -37,7 +39,10 @@ async fn prepare_all(
         }
         let location = location?.to_string();
 
-        state.cache_pool.prepare(&location).await;
+        state
+            .cache_pool
+            .prepare(&location, Some(origin.as_ref()))
+            .await;
         media.location = format!(
             "{}/stream?origin={}",
             base_url,
@@ -49,30 +54,90 @@ async fn prepare_all(
     Ok(())
 }
 
+/// Fetch and parse a playlist, its bytes are returned to keep it as the last good one
+async fn fetch_playlist(
+    state: &AppStateRef,
+    origin: &str,
+) -> Result<(M3uPlaylist, CacheResource), anyhow::Error> {
+    let response = state.http_client.get(origin).send().await?;
+    if !response.status().is_success() {
+        return Err(DownloadError::RequestNotSuccess(response.status().as_u16()).into());
+    }
+
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or("application/vnd.apple.mpegurl")
+        .to_owned();
+    let bytes = response.bytes().await?;
+    let playlist = parse_m3u8_async(Cursor::new(bytes.clone())).await?;
+
+    Ok((
+        playlist,
+        CacheResource {
+            bytes,
+            content_type,
+        },
+    ))
+}
+
+/// The last good playlist, if the origin failed with a retryable error
+async fn get_stale_playlist(
+    state: &AppStateRef,
+    origin: &str,
+    e: &anyhow::Error,
+) -> Option<M3uPlaylist> {
+    if e.downcast_ref::<DownloadError>()
+        .is_some_and(|x| !x.class().is_retryable())
+    {
+        return None;
+    }
+    let resource = state.playlists.get(origin)?;
+    let playlist = parse_m3u8_async(Cursor::new(resource.bytes)).await.ok()?;
+
+    warn!("Fetch media {}: {}, serving the stale copy", origin, e);
+    state
+        .metrics
+        .stale_served
+        .with_label_values(&["playlist"])
+        .inc();
+    Some(playlist)
+}
+
 pub async fn get_media(
     State(state): State<AppStateRef>,
     Query(query): Query<MediaQuery>,
 ) -> Result<Response, StatusCode> {
-    let data = state
-        .http_client
-        .get(&query.origin)
-        .send()
-        .await
-        .map_err(internal_error_with_log!("Request media"))?
-        .bytes()
-        .await
-        .map_err(internal_error_with_log!("Request bytes"))?;
+    let start = Instant::now();
+    let mut playlist = match fetch_playlist(&state, &query.origin).await {
+        Ok((playlist, resource)) => {
+            state.playlists.put(query.origin.clone(), resource);
+            playlist
+        }
+        Err(e) => match get_stale_playlist(&state, &query.origin, &e).await {
+            Some(playlist) => playlist,
+            None => return Err(internal_error_with_log!("Fetch media")(e)),
+        },
+    };
 
     state.tracking_pool.track(&query.origin).await;
-
-    // parse
-    let mut playlist = parse_m3u8_async(Cursor::new(data))
-        .await
-        .map_err(internal_error_with_log!("Parse m3u8"))?;
+    state
+        .metrics
+        .playlist_refresh_duration
+        .with_label_values(&["media"])
+        .observe(start.elapsed().as_secs_f64());
 
     prepare_all(&state, &mut playlist, query.origin)
         .await
         .map_err(internal_error_with_log!("Start caching"))?;
 
-    Ok(playlist.to_string().into_response())
+    let playlist = playlist.to_string();
+    state
+        .metrics
+        .served_bytes
+        .with_label_values(&["media"])
+        .inc_by(playlist.len() as u64);
+
+    Ok(playlist.into_response())
 }
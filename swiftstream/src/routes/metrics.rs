@@ -0,0 +1,31 @@
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use prometheus::TEXT_FORMAT;
+
+use crate::AppStateRef;
+
+pub async fn get_metrics(State(state): State<AppStateRef>) -> Response {
+    // gauges are sampled on scrape
+    let (items, bytes) = state.cache_pool.get_stats().await;
+    state.metrics.cache_items.set(items as i64);
+    state.metrics.cache_bytes.set(bytes as i64);
+    if let Some(stats) = state.cache_pool.get_content_stats() {
+        state
+            .metrics
+            .dedupe_saved_bytes
+            .set(stats.saved_bytes as i64);
+    }
+    state
+        .metrics
+        .tracked_streams
+        .set(state.tracking_pool.get_count().await as i64);
+
+    (
+        [(header::CONTENT_TYPE, TEXT_FORMAT)],
+        state.metrics.render(),
+    )
+        .into_response()
+}
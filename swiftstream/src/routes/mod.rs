@@ -2,17 +2,43 @@ use axum::{Router, routing::get};
 
 use crate::AppStateRef;
 
+mod admin;
 mod media;
+mod metrics;
 mod playlist;
 mod stream;
 
 pub fn get_routes(app_state: &AppStateRef) -> Router {
-    Router::new()
+    let mut router = Router::new();
+    if let Some(admin) = &app_state.config.admin {
+        router = router.nest(&admin.prefix, admin::get_routes(app_state));
+    }
+
+    router
         .route("/playlist", get(playlist::get_playlist))
         .route(
             "/stream",
             get(stream::get_stream).head(stream::get_stream_head),
         )
         .route("/media", get(media::get_media))
+        .route("/metrics", get(metrics::get_metrics))
         .with_state(app_state.clone())
 }
+
+/// Serve `router` on a free local port, returns its base URL
+#[cfg(test)]
+async fn serve_local(router: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    base_url
+}
+
+/// Serve the routes of an app configured by `config`, returns its state and base URL
+#[cfg(test)]
+async fn serve_app(config: &str) -> (AppStateRef, String) {
+    let config = serde_yaml::from_str(config).unwrap();
+    let app_state = std::sync::Arc::new(crate::AppState::new(config));
+    let base_url = serve_local(get_routes(&app_state)).await;
+    (app_state, base_url)
+}
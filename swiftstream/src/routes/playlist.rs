@@ -5,8 +5,10 @@ use axum::{
     response::{IntoResponse, Response},
 };
 use log::warn;
+use mediastream_rs::Expander;
 use reqwest::StatusCode;
 use serde::Deserialize;
+use smol_str::SmolStr;
 use url::Url;
 
 use crate::{AppStateRef, internal_error_with_log, transfer::parse_m3u8_async};
@@ -14,6 +16,9 @@ use crate::{AppStateRef, internal_error_with_log, transfer::parse_m3u8_async};
 #[derive(Deserialize)]
 pub struct PlaylistQuery {
     pub origin: String,
+    /// Inline the child playlists of a "list of lists"
+    #[serde(default)]
+    pub expand: bool,
 }
 pub async fn get_playlist(
     State(state): State<AppStateRef>,
@@ -33,13 +38,31 @@ pub async fn get_playlist(
         .await
         .map_err(internal_error_with_log!("Parse m3u8"))?;
 
+    if query.expand {
+        let http_client = state.http_client.clone();
+        let mut expander = Expander::new(move |location: SmolStr| {
+            let http_client = http_client.clone();
+            async move {
+                http_client
+                    .get(location.as_str())
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }
+        });
+
+        let expanded = expander.expand(playlist, Some(&query.origin)).await;
+        for failure in expanded.failures.iter() {
+            warn!("Failed to expand {}: {}", failure.location, failure.error);
+        }
+        playlist = expanded.playlist;
+    }
+
     let base = Url::parse(&query.origin).map_err(internal_error_with_log!("Parse url"))?;
 
-    let base_url = state
-        .config
-        .base_url
-        .clone()
-        .unwrap_or_else(|| String::new());
+    let base_url = state.config.base_url.clone().unwrap_or_default();
 
     for media in playlist.medias.iter_mut() {
         let media_location = media.location.clone();
@@ -83,5 +106,12 @@ pub async fn get_playlist(
         };
     }
 
-    Ok(playlist.to_string().into_response())
+    let playlist = playlist.to_string();
+    state
+        .metrics
+        .served_bytes
+        .with_label_values(&["playlist"])
+        .inc_by(playlist.len() as u64);
+
+    Ok(playlist.into_response())
 }
@@ -1,20 +1,16 @@
-use std::io::{Cursor, Seek, SeekFrom};
-
 use axum::{
     body::Body,
     extract::{Query, State},
-    http::{HeaderMap, StatusCode, header},
+    http::{HeaderMap, Method, StatusCode, header},
     response::{IntoResponse, Response},
 };
 
-use futures::stream::select_all;
 use serde::Deserialize;
-use tokio::io::{self, AsyncReadExt};
-use tokio_util::io::ReaderStream;
+use tokio::io;
 
 use crate::{
     AppStateRef, bad_request_with_log, internal_error_with_log,
-    transfer::{HttpRange, parse_http_ranges},
+    transfer::{HttpRange, parse_http_ranges, passthrough},
 };
 
 #[derive(Deserialize)]
@@ -22,18 +18,52 @@ pub struct StreamQuery {
     pub origin: String,
 }
 
+/// The status of a failed load, usually the one of the origin server
+fn failure_status(status: u16) -> StatusCode {
+    StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY)
+}
+
+/// The pool is full, serve the client without caching
+async fn bypass_cache(
+    state: &AppStateRef,
+    method: Method,
+    origin: String,
+    headers: &HeaderMap,
+) -> Result<Response, StatusCode> {
+    if state.config.redirect_when_full {
+        return Response::builder()
+            .header(header::LOCATION, origin)
+            .status(StatusCode::TEMPORARY_REDIRECT)
+            .body(Body::empty())
+            .map_err(internal_error_with_log!("OOM, Redirect"));
+    }
+
+    let response = passthrough(&state.http_client, method, &origin, headers)
+        .await
+        .map_err(internal_error_with_log!("OOM, Pass through"))?;
+    Ok(response.map(|x| state.metrics.count_body("passthrough", x)))
+}
+
+/// The bytes `[from, to)` a range asks for out of `length`, `None` if none of them exist
+fn resolve_range(range: &HttpRange, length: usize) -> Option<(usize, usize)> {
+    let (from, to) = match *range {
+        HttpRange::Suffix(len) => (length.saturating_sub(len as usize), length),
+        HttpRange::Prefix(from) => (from as usize, length),
+        HttpRange::Range(from, to) => (from as usize, (to as usize).saturating_add(1)),
+    };
+    let to = to.min(length);
+    (from < to).then_some((from, to))
+}
+
 pub async fn get_stream_head(
     State(state): State<AppStateRef>,
     Query(query): Query<StreamQuery>,
+    headers: HeaderMap,
 ) -> Result<Response, StatusCode> {
-    let data = match state.cache_pool.get(&query.origin).await {
+    let buffer = match state.cache_pool.get(&query.origin).await {
         Err(e) => {
             if e.kind() == io::ErrorKind::OutOfMemory {
-                return Ok(axum::http::Response::builder()
-                    .header(header::LOCATION, query.origin)
-                    .status(StatusCode::TEMPORARY_REDIRECT)
-                    .body(Body::empty())
-                    .map_err(internal_error_with_log!("OOM, Redirect"))?);
+                return bypass_cache(&state, Method::HEAD, query.origin, &headers).await;
             } else {
                 return Err(internal_error_with_log!("Query cache pool")(e));
             }
@@ -41,9 +71,12 @@ pub async fn get_stream_head(
         Ok(v) => v,
     };
 
+    let head = buffer.wait_head().await.map_err(failure_status)?;
+    let length = buffer.wait_length().await.map_err(failure_status)?;
+
     Ok(([
-        (header::CONTENT_TYPE, data.content_type),
-        (header::CONTENT_LENGTH, data.bytes.len().to_string()),
+        (header::CONTENT_TYPE, head.content_type),
+        (header::CONTENT_LENGTH, length.to_string()),
         (header::ACCEPT_RANGES, "bytes".to_string()),
     ])
     .into_response())
@@ -54,14 +87,10 @@ pub async fn get_stream(
     Query(query): Query<StreamQuery>,
     headers: HeaderMap,
 ) -> Result<Response, StatusCode> {
-    let data = match state.cache_pool.get(&query.origin).await {
+    let buffer = match state.cache_pool.get(&query.origin).await {
         Err(e) => {
             if e.kind() == io::ErrorKind::OutOfMemory {
-                return Ok(axum::http::Response::builder()
-                    .header(header::LOCATION, query.origin)
-                    .status(StatusCode::TEMPORARY_REDIRECT)
-                    .body(Body::empty())
-                    .map_err(internal_error_with_log!("OOM, Redirect"))?);
+                return bypass_cache(&state, Method::GET, query.origin, &headers).await;
             } else {
                 return Err(internal_error_with_log!("Query cache pool")(e));
             }
@@ -69,6 +98,9 @@ pub async fn get_stream(
         Ok(v) => v,
     };
 
+    // wait for the response of origin server only, the body is streamed while downloading
+    let head = buffer.wait_head().await.map_err(failure_status)?;
+
     // is it a Range request?
     let ranges = if let Some(range) = headers.get(header::RANGE) {
         let range_str = range
@@ -81,45 +113,120 @@ pub async fn get_stream(
         None
     };
 
-    if let Some(ranges) = ranges {
-        let body = select_all(
-            ranges
-                .into_iter()
-                .map(|x| (x, Cursor::new(data.bytes.clone())))
-                .map(|mut x| match x.0 {
-                    HttpRange::Suffix(len) => x.1.take(len),
-                    HttpRange::Prefix(len) => {
-                        _ = x.1.seek(SeekFrom::Start(len));
-                        x.1.take(u64::MAX)
-                    }
-                    HttpRange::Range(from, to) => {
-                        _ = x.1.seek(SeekFrom::Start(from));
-                        x.1.take(to - from)
-                    }
-                })
-                .map(|x| ReaderStream::new(x)),
-        );
+    // a single range is served, the whole body is sent for several, as RFC 9110 allows
+    if let Some([range]) = ranges.as_deref() {
+        let length = buffer.wait_length().await.map_err(failure_status)? as usize;
+
+        let Some((from, to)) = resolve_range(range, length) else {
+            return Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", length))
+                .body(Body::empty())
+                .map_err(internal_error_with_log!("Generate range response"));
+        };
 
         let response = Response::builder()
-            .header(header::CONTENT_TYPE, data.content_type)
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_TYPE, head.content_type)
             .header(header::ACCEPT_RANGES, "bytes")
-            .body(Body::from_stream(body))
+            .header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", from, to - 1, length),
+            )
+            .header(header::CONTENT_LENGTH, to - from)
+            .body(
+                state
+                    .metrics
+                    .count_body("stream", Body::from_stream(buffer.stream(from, Some(to)))),
+            )
             .map_err(internal_error_with_log!("Generate range response"))?;
 
-        return Ok(response);
+        Ok(response)
     } else {
-        // send all
-        let length = data.bytes.len();
-        let body = ReaderStream::new(Cursor::new(data.bytes));
-
-        return Ok((
-            [
-                (header::CONTENT_TYPE, data.content_type),
-                (header::CONTENT_LENGTH, length.to_string()),
-                (header::ACCEPT_RANGES, "bytes".to_string()),
-            ],
-            Body::from_stream(body),
-        )
-            .into_response());
+        // send all, the length is unknown if the origin server didn't tell
+        let mut response = Response::builder()
+            .header(header::CONTENT_TYPE, head.content_type)
+            .header(header::ACCEPT_RANGES, "bytes");
+        if let Some(length) = head.content_length {
+            response = response.header(header::CONTENT_LENGTH, length);
+        }
+
+        response
+            .body(
+                state
+                    .metrics
+                    .count_body("stream", Body::from_stream(buffer.stream(0, None))),
+            )
+            .map_err(internal_error_with_log!("Generate response"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, http::StatusCode, routing::get};
+    use reqwest::{Client, header};
+    use tokio::runtime::Runtime;
+
+    use crate::routes::{serve_app, serve_local};
+
+    #[test]
+    fn test_ranges() {
+        let body = (0..1000).map(|x| (x % 251) as u8).collect::<Vec<_>>();
+        let origin_body = body.clone();
+
+        Runtime::new().unwrap().block_on(async {
+            let origin =
+                serve_local(Router::new().route("/0.ts", get(|| async move { origin_body }))).await;
+            let (_, base_url) = serve_app("listenAddr: 127.0.0.1:0").await;
+            let url = format!(
+                "{}/stream?origin={}",
+                base_url,
+                urlencoding::encode(&format!("{}/0.ts", origin))
+            );
+
+            let client = Client::new();
+            let get = async |range: Option<&str>| {
+                let mut request = client.get(&url);
+                if let Some(range) = range {
+                    request = request.header(header::RANGE, range);
+                }
+                let response = request.send().await.unwrap();
+                let content_range = response
+                    .headers()
+                    .get(header::CONTENT_RANGE)
+                    .map(|x| x.to_str().unwrap().to_owned());
+                (
+                    response.status(),
+                    content_range,
+                    response.bytes().await.unwrap(),
+                )
+            };
+
+            let (status, content_range, bytes) = get(Some("bytes=0-99")).await;
+            assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+            assert_eq!(content_range.unwrap(), "bytes 0-99/1000");
+            assert_eq!(bytes, body[..100]);
+
+            let (status, content_range, bytes) = get(Some("bytes=-100")).await;
+            assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+            assert_eq!(content_range.unwrap(), "bytes 900-999/1000");
+            assert_eq!(bytes, body[900..]);
+
+            let (_, content_range, bytes) = get(Some("bytes=990-2000")).await;
+            assert_eq!(content_range.unwrap(), "bytes 990-999/1000");
+            assert_eq!(bytes, body[990..]);
+
+            let (status, content_range, _) = get(Some("bytes=1000-")).await;
+            assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+            assert_eq!(content_range.unwrap(), "bytes */1000");
+
+            // several ranges, or none
+            for range in [Some("bytes=0-1,5-6"), None] {
+                let (status, content_range, bytes) = get(range).await;
+                assert_eq!(status, StatusCode::OK);
+                assert!(content_range.is_none());
+                assert_eq!(bytes, body);
+            }
+        });
     }
 }
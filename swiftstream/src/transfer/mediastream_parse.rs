@@ -38,10 +38,8 @@ pub async fn parse_m3u8_async(
 ) -> Result<M3uPlaylist, ParseM3U8Error> {
     Ok(tokio::task::spawn_blocking(move || {
         let mut parser = mediastream_rs::Parser::new(stream);
-        if let Err(e) = parser.parse() {
-            return Err(e);
-        }
-        Ok(parser.get_playlist())
+        parser.parse()?;
+        Ok::<_, ParseError>(parser.get_playlist())
     })
     .await??)
 }
@@ -1,7 +1,9 @@
 mod mediastream_parse;
+mod passthrough;
 mod proxy_manager;
 mod range;
 
 pub use mediastream_parse::*;
+pub use passthrough::*;
 pub use proxy_manager::*;
 pub use range::*;
@@ -0,0 +1,58 @@
+use axum::{
+    body::Body,
+    http::{HeaderMap, Method, header},
+    response::Response,
+};
+use futures::stream;
+use reqwest::Client;
+
+/// Request headers forwarded to the origin server
+const FORWARDED_REQUEST_HEADERS: [header::HeaderName; 3] =
+    [header::RANGE, header::IF_RANGE, header::ACCEPT];
+
+/// Response headers forwarded back to the client
+const FORWARDED_RESPONSE_HEADERS: [header::HeaderName; 6] = [
+    header::CONTENT_TYPE,
+    header::CONTENT_LENGTH,
+    header::CONTENT_RANGE,
+    header::ACCEPT_RANGES,
+    header::LAST_MODIFIED,
+    header::ETAG,
+];
+
+/// Fetch the origin with our own client (so the proxies and user agent are applied),
+/// and stream the response back without storing it
+pub async fn passthrough(
+    http_client: &Client,
+    method: Method,
+    origin: impl AsRef<str>,
+    headers: &HeaderMap,
+) -> Result<Response, reqwest::Error> {
+    let mut request = http_client.request(method, origin.as_ref());
+    for name in FORWARDED_REQUEST_HEADERS.iter() {
+        if let Some(value) = headers.get(name) {
+            request = request.header(name, value);
+        }
+    }
+
+    let origin_response = request.send().await?;
+
+    let mut response = Response::builder().status(origin_response.status());
+    for name in FORWARDED_RESPONSE_HEADERS.iter() {
+        if let Some(value) = origin_response.headers().get(name) {
+            response = response.header(name, value);
+        }
+    }
+
+    let body = stream::unfold(Some(origin_response), |x| async move {
+        let mut x = x?;
+        match x.chunk().await {
+            Ok(Some(chunk)) => Some((Ok(chunk), Some(x))),
+            Ok(None) => None,
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+
+    // only the status and headers are set, it never fails
+    Ok(response.body(Body::from_stream(body)).unwrap())
+}
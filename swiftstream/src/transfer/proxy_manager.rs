@@ -8,6 +8,12 @@ pub struct ProxyManager {
     fallback_proxy: Option<Url>,
 }
 
+impl Default for ProxyManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ProxyManager {
     pub fn new() -> Self {
         Self {
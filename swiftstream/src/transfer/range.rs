@@ -48,25 +48,25 @@ pub fn parse_http_ranges(value: impl AsRef<str>) -> Result<Vec<HttpRange>, HttpR
 
         let from = range_vec[0];
         let to = range_vec[1];
-        if from.len() == 0 {
+        if from.is_empty() {
             // suffix-length
             result.push(HttpRange::Suffix(
-                str::parse(to).map_err(|e| HttpRangeParseError::InvalidNumber(e))?,
+                str::parse(to).map_err(HttpRangeParseError::InvalidNumber)?,
             ));
             break; // only 1 suffix-length
         }
 
-        if to.len() == 0 {
+        if to.is_empty() {
             // range-start
             result.push(HttpRange::Prefix(
-                str::parse(from).map_err(|e| HttpRangeParseError::InvalidNumber(e))?,
+                str::parse(from).map_err(HttpRangeParseError::InvalidNumber)?,
             ));
             break; // only 1 range-start
         }
 
         result.push(HttpRange::Range(
-            str::parse(from).map_err(|e| HttpRangeParseError::InvalidNumber(e))?,
-            str::parse(to).map_err(|e| HttpRangeParseError::InvalidNumber(e))?,
+            str::parse(from).map_err(HttpRangeParseError::InvalidNumber)?,
+            str::parse(to).map_err(HttpRangeParseError::InvalidNumber)?,
         ));
     }
 
@@ -10,6 +10,14 @@ use crate::{Error, ErrorKind};
 #[derive(Clone)]
 pub struct Container<'a>(Arc<RwLock<ContainerImpl<'a>>>);
 
+impl<'a> Default for Container<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the services and constructors aren't `Send`, `Arc` keeps the public type as it was
+#[allow(clippy::arc_with_non_send_sync)]
 impl<'a> Container<'a> {
     pub fn new() -> Self {
         Self(Arc::new(RwLock::new(ContainerImpl::default())))
@@ -22,7 +30,7 @@ impl<'a> Container<'a> {
         }
         impl_obj
             .services
-            .insert(boxed_service.type_id.clone(), boxed_service);
+            .insert(boxed_service.type_id, boxed_service);
 
         Ok(())
     }
@@ -58,7 +66,7 @@ impl<'a> Container<'a> {
         };
 
         // add current type into pending
-        self.0.write()?.pending_construction.insert(type_id.clone());
+        self.0.write()?.pending_construction.insert(type_id);
 
         // construct the object
         let construction = constructor.construct::<T>(self.clone());
@@ -201,9 +209,11 @@ impl<T: Clone + 'static> From<T> for BoxedService {
     }
 }
 
+type ConstructorFn<'a> = dyn Fn(Container) -> Box<dyn Any> + 'a;
+
 struct BoxedConstructor<'a> {
     pub type_id: TypeId,
-    pub value: Box<dyn Fn(Container) -> Box<dyn Any> + 'a>,
+    pub value: Box<ConstructorFn<'a>>,
 }
 
 impl<'a> BoxedConstructor<'a> {
@@ -235,7 +245,7 @@ mod tests {
     fn basic_register() {
         let c = Container::new();
         c.register_service("A".to_string());
-        c.register_service(123 as u64);
+        c.register_service(123_u64);
 
         assert_eq!(c.get::<String>(), "A");
         assert_eq!(c.get::<u64>(), 123);
@@ -245,7 +255,7 @@ mod tests {
     fn basic_constructor() {
         let c = Container::new();
         c.register_constructor(|_| "A".to_string());
-        c.register_constructor(|_| 123 as u64);
+        c.register_constructor(|_| 123_u64);
 
         assert_eq!(c.get::<String>(), "A");
         assert_eq!(c.get::<u64>(), 123);
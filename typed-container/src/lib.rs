@@ -1,39 +1,35 @@
 //! A simple container for IoC
 //! Example:
 //! ```
-//! let config = /* ... */;
-//! let container = Container::new();
-//! container.register_constructor(|_| Arc::new(Client::new()));
+//! use std::sync::Arc;
 //!
-//! let config_cloned = config.clone();
-//! container.register_constructor(move |x| {
-//!     Arc::new(Downloader::new(
-//!         x.get(),
-//!         config_cloned.download_threads.unwrap_or(1), // default single thread
-//!     ))
-//! });
+//! use typed_container::Container;
 //!
-//! let config_cloned = config.clone();
-//! container.register_constructor(move |x| {
-//!     CachePool::new(
-//!         config_cloned.size_limit.unwrap_or(512 * 1024 * 1024), // 512MB
-//!         config_cloned.cache_expire.unwrap_or(30),              // 30s
-//!         x.get(),
-//!     )
-//! });
+//! struct Config {
+//!     download_threads: u8,
+//! }
+//! struct Downloader {
+//!     threads: u8,
+//! }
+//! struct CachePool {
+//!     downloader: Arc<Downloader>,
+//! }
 //!
-//! let config_cloned = config.clone();
-//! container.register_constructor(move |x| {
-//!     StreamTrackingPool::new(
-//!         config_cloned.track_expire.unwrap_or(60),  // 60s
-//!         config_cloned.track_interval.unwrap_or(8), // 8s
-//!         x.get(),
-//!         x.get(),
-//!     )
+//! let container = Container::new();
+//! container.register_service(Arc::new(Config { download_threads: 4 }));
+//!
+//! container.register_constructor(|x| {
+//!     let config = x.get::<Arc<Config>>();
+//!     Arc::new(Downloader {
+//!         threads: config.download_threads,
+//!     })
 //! });
 //!
-//! let _cache_pool = container::get::<Arc<CachePool>>();
-//! // ...
+//! // dependencies are constructed when first needed
+//! container.register_constructor(|x| Arc::new(CachePool { downloader: x.get() }));
+//!
+//! let cache_pool = container.get::<Arc<CachePool>>();
+//! assert_eq!(cache_pool.downloader.threads, 4);
 //! ```
 
 mod container;